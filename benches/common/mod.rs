@@ -0,0 +1,61 @@
+//! Hand assembled programmes shared by the benchmarks.
+// Not every benchmark uses every fixture
+#![allow(dead_code)]
+
+use synacor::{SynCpu, Data};
+use synacor::cpu::assembler;
+
+/// Counts r0 down from 32767, accumulating into r1 through the stack and a call,
+/// looping forever so that any number of steps can be taken.
+pub const LOOP: &str = "
+    set r0 32767
+    add r0 r0 32767
+    mult r1 r0 3
+    push r1
+    call 0x0016
+    pop r2
+    jt r0 0x0003
+    jmp 0x0000
+    ret
+";
+
+/// The recursive check performed by the teleporter, evaluated for r0 = 3,
+/// r1 = 2 and r7 = 1 before halting.
+pub const TELEPORTER: &str = "
+    set r0 3
+    set r1 2
+    set r7 1
+    call 0x000c
+    halt
+    ; 0x000c: the check itself
+    jt r0 0x0014
+    add r0 r1 1
+    ret
+    ; 0x0014: r0 != 0, r1 == 0
+    jt r1 0x0021
+    add r0 r0 32767
+    set r1 r7
+    call 0x000c
+    ret
+    ; 0x0021: r0 != 0, r1 != 0
+    push r0
+    add r1 r1 32767
+    call 0x000c
+    set r1 r0
+    pop r0
+    add r0 r0 32767
+    call 0x000c
+    ret
+";
+
+/// Assembles `source` into a programme binary.
+pub fn binary(source: &str) -> Vec<u8> {
+    assembler::to_bytes(&assembler::assemble(source).unwrap())
+}
+
+/// Creates a CPU with `source` loaded at address 0.
+pub fn cpu(source: &str) -> SynCpu {
+    let mut cpu = SynCpu::new(Data::from_words(&assembler::assemble(source).unwrap()).unwrap());
+    cpu.loud = false;
+    cpu
+}
@@ -0,0 +1,34 @@
+//! Compares `SynCpu::step` against the table driven `SynCpu::step_dispatch`.
+
+#[macro_use] extern crate criterion;
+extern crate synacor;
+
+mod common;
+
+use criterion::Criterion;
+
+/// The number of instructions evaluated per iteration.
+const STEPS: usize = 10_000;
+
+fn bench_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+
+    let mut cpu_match = common::cpu(common::LOOP);
+    group.bench_function("match", |b| b.iter(|| {
+        for _ in 0..STEPS {
+            cpu_match.step();
+        }
+    }));
+
+    let mut cpu_table = common::cpu(common::LOOP);
+    group.bench_function("table", |b| b.iter(|| {
+        for _ in 0..STEPS {
+            cpu_table.step_dispatch();
+        }
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_step);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Throughput of the VM on small hand assembled programmes, and the cost of
+//! loading and resetting a CPU.
+
+#[macro_use] extern crate criterion;
+extern crate synacor;
+
+mod common;
+
+use criterion::{Criterion, Throughput, black_box};
+
+use synacor::{SynCpu, Data};
+
+/// The number of instructions evaluated per iteration of the step benchmark.
+const STEPS: u64 = 10_000;
+
+fn bench_steps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("steps");
+    group.throughput(Throughput::Elements(STEPS));
+
+    let mut cpu = common::cpu(common::LOOP);
+    group.bench_function("arithmetic loop", |b| b.iter(|| {
+        for _ in 0..STEPS {
+            cpu.step_dispatch();
+        }
+    }));
+
+    group.finish();
+}
+
+fn bench_teleporter(c: &mut Criterion) {
+    let mut cpu = common::cpu(common::TELEPORTER);
+    c.bench_function("teleporter", |b| b.iter(|| {
+        cpu.reset();
+        cpu.run_headless();
+        black_box(cpu.data.registers[0])
+    }));
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    let binary = common::binary(common::TELEPORTER);
+
+    group.bench_function("from_bin", |b| b.iter(|| {
+        Data::from_bin(black_box(&binary)).unwrap()
+    }));
+
+    group.bench_function("rebuild", |b| b.iter(|| {
+        SynCpu::new(Data::from_bin(black_box(&binary)).unwrap())
+    }));
+
+    let mut cpu = SynCpu::new(Data::from_bin(&binary).unwrap());
+    group.bench_function("reset", |b| b.iter(|| cpu.reset()));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_steps, bench_teleporter, bench_load);
+criterion_main!(benches);
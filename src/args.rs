@@ -0,0 +1,71 @@
+//! Command line arguments
+//!
+//! A small parser shared by the executables, included with `#[path]` so that the
+//! library itself doesn't grow a command line interface. Options and flags may be
+//! given in any order, before or after the positional arguments.
+
+// Not every executable uses every accessor.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// The parsed command line, without the name of the executable.
+pub struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    /// Parses `args`, where each of `options` takes a value and each of `flags` doesn't.
+    /// Anything else starting with `--` is an error, as is an option without a value
+    /// or an option given twice.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I, options: &[&str], flags: &[&str]) -> Result<Args, String> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: HashSet::new(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if options.contains(&arg.as_str()) {
+                let val = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                if parsed.options.insert(arg.clone(), val).is_some() {
+                    return Err(format!("{} was given more than once", arg));
+                }
+            } else if flags.contains(&arg.as_str()) {
+                parsed.flags.insert(arg);
+            } else if arg.starts_with("--") {
+                return Err(format!("Unknown option {}", arg));
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the arguments that aren't options or flags, in order.
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Returns the value given for `option`, if any.
+    pub fn option(&self, option: &str) -> Option<&str> {
+        self.options.get(option).map(|val| val.as_str())
+    }
+
+    /// Parses the value given for `option`, if any.
+    pub fn parsed<T: FromStr>(&self, option: &str) -> Result<Option<T>, String> {
+        match self.option(option) {
+            Some(val) => val.parse().map(Some).map_err(|_| format!("Invalid value for {}: {}", option, val)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if `flag` was given.
+    pub fn flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+}
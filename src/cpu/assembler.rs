@@ -0,0 +1,168 @@
+//! Assembler
+//!
+//! Converts the mnemonic syntax emitted by the disassembler back into a
+//! sequence of words. Each non-empty line holds a single instruction, optionally
+//! prefixed by the `0x1234:` address column the disassembler prints. Operands are
+//! registers (`r0`-`r7`), decimal or `0x`-prefixed hexadecimal literals, or quoted
+//! characters such as `'a'`. Anything following a `;` is treated as a comment.
+
+use std::error;
+use std::fmt;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use cpu::syn_int::SynInt;
+
+/// An error encountered while assembling, tagged with the (1-based) line number.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AssembleError {
+    /// The mnemonic isn't a known instruction
+    UnknownMnemonic(usize, String),
+    /// The instruction was given the wrong number of operands
+    OperandCount(usize, usize, usize),
+    /// An operand could not be parsed as a register or literal
+    InvalidOperand(usize, String),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AssembleError::*;
+        match *self {
+            UnknownMnemonic(line, ref m) => write!(f, "line {}: unknown mnemonic `{}`", line, m),
+            OperandCount(line, expected, found) => write!(f, "line {}: expected {} operands, found {}",
+                                                          line, expected, found),
+            InvalidOperand(line, ref op) => write!(f, "line {}: invalid operand `{}`", line, op),
+        }
+    }
+}
+
+impl error::Error for AssembleError {
+    fn description(&self) -> &str {
+        use self::AssembleError::*;
+        match *self {
+            UnknownMnemonic(..) => "Unknown mnemonic",
+            OperandCount(..) => "Wrong number of operands",
+            InvalidOperand(..) => "Invalid operand",
+        }
+    }
+}
+
+/// The mnemonic used in the architecture specification for each opcode.
+const MNEMONICS: [&str; 22] = [
+    "halt", "set", "push", "pop", "eq", "gt", "jmp", "jt", "jf", "add", "mult",
+    "mod", "and", "or", "not", "rmem", "wmem", "call", "ret", "out", "in", "noop",
+];
+
+/// Returns the architecture specification's mnemonic for an opcode, or `None` if
+/// the opcode isn't one of the 22 instructions.
+pub fn mnemonic(opcode: u16) -> Option<&'static str> {
+    MNEMONICS.get(opcode as usize).cloned()
+}
+
+/// Returns the opcode and operand count for a mnemonic. Both the names used in
+/// the architecture specification and those printed by the disassembler are accepted.
+pub fn opcode(mnemonic: &str) -> Option<(u16, usize)> {
+    let op = match mnemonic {
+        "halt" => (0, 0),
+        "set" => (1, 2),
+        "push" => (2, 1),
+        "pop" => (3, 1),
+        "eq" => (4, 3),
+        "gt" => (5, 3),
+        "jmp" => (6, 1),
+        "jt" | "jmnz" => (7, 2),
+        "jf" | "jmpz" => (8, 2),
+        "add" => (9, 3),
+        "mult" | "mul" => (10, 3),
+        "mod" => (11, 3),
+        "and" => (12, 3),
+        "or" => (13, 3),
+        "not" => (14, 2),
+        "rmem" => (15, 2),
+        "wmem" => (16, 2),
+        "call" => (17, 1),
+        "ret" => (18, 0),
+        "out" => (19, 1),
+        "in" => (20, 1),
+        "noop" => (21, 0),
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Parses a single operand into its encoded word.
+fn operand(token: &str) -> Option<u16> {
+    if token.len() >= 3 && token.starts_with('\'') && token.ends_with('\'') {
+        let mut chars = token[1..token.len() - 1].chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) if (c as u32) < 32768 => Some(c as u16),
+            _ => None,
+        };
+    }
+
+    token.parse::<SynInt>().ok().map(u16::from)
+}
+
+/// Splits a line into tokens on whitespace, keeping quoted characters
+/// (which may themselves be spaces) together and dropping comments.
+fn tokenize(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() && !rest.starts_with(';') {
+        let len = if rest.starts_with('\'') {
+            rest.char_indices()
+                .skip(2)
+                .find(|&(_, c)| c == '\'')
+                .map(|(i, _)| i + 1)
+                .unwrap_or(rest.len())
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+/// Assembles mnemonic source text into a sequence of words.
+pub fn assemble(source: &str) -> Result<Vec<u16>, AssembleError> {
+    let mut words = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut tokens = tokenize(line);
+
+        // Skip the address column printed by the disassembler
+        if tokens.first().is_some_and(|t| t.ends_with(':')) {
+            tokens.remove(0);
+        }
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let (op, count) = opcode(tokens[0])
+            .ok_or_else(|| AssembleError::UnknownMnemonic(line_no, tokens[0].to_string()))?;
+        if tokens.len() - 1 != count {
+            return Err(AssembleError::OperandCount(line_no, count, tokens.len() - 1));
+        }
+
+        words.push(op);
+        for token in &tokens[1..] {
+            let word = operand(token)
+                .ok_or_else(|| AssembleError::InvalidOperand(line_no, token.to_string()))?;
+            words.push(word);
+        }
+    }
+
+    Ok(words)
+}
+
+/// Converts words into the little-endian byte format read by `Data::from_bin`.
+pub fn to_bytes(words: &[u16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(words.len() * 2);
+    for word in words {
+        bytes.write_u16::<LittleEndian>(*word).unwrap();
+    }
+    bytes
+}
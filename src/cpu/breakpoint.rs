@@ -0,0 +1,38 @@
+//! Breakpoints
+
+/// The state of a breakpoint, kept in `SynCpu::breakpoints` by address.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Disabled breakpoints are kept but never stop execution.
+    pub enabled: bool,
+    /// The number of times running has reached this breakpoint while it was enabled.
+    pub hits: u64,
+    /// The number of further hits to pass over before stopping.
+    pub ignore: u64,
+}
+
+impl Breakpoint {
+    /// Creates an enabled breakpoint that passes over its first `ignore` hits.
+    pub fn new(ignore: u64) -> Breakpoint {
+        Breakpoint {
+            enabled: true,
+            hits: 0,
+            ignore,
+        }
+    }
+
+    /// Records a hit, returning true if execution should stop here.
+    pub fn hit(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            false
+        } else {
+            true
+        }
+    }
+}
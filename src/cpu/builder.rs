@@ -0,0 +1,110 @@
+//! CPU builder
+//!
+//! Sets up a `SynCpu`'s input, output and limits in one expression instead of
+//! assigning its fields one by one after `SynCpu::new`.
+
+use std::collections::VecDeque;
+
+use cpu::{Data, Injection, SynCpu};
+use cpu::injection::InjectionError;
+
+/// A builder for a configured `SynCpu`, created with `SynCpu::builder`.
+///
+/// Anything not set is left as `SynCpu::new` leaves it.
+pub struct SynCpuBuilder {
+    data: Data,
+    input: VecDeque<char>,
+    read_stdin: bool,
+    capture_output: bool,
+    loud: bool,
+    max_steps: Option<u64>,
+    max_stack_depth: Option<usize>,
+    /// The first injection that couldn't be applied, reported by `build`
+    error: Option<InjectionError>,
+}
+
+impl SynCpuBuilder {
+    /// Creates a builder for a CPU running `data`.
+    pub fn new(data: Data) -> SynCpuBuilder {
+        SynCpuBuilder {
+            data,
+            input: VecDeque::new(),
+            read_stdin: true,
+            capture_output: false,
+            loud: true,
+            max_steps: None,
+            max_stack_depth: None,
+            error: None,
+        }
+    }
+
+    /// Queues `input` to be read by `in` before anything from stdin.
+    pub fn input(mut self, input: &str) -> SynCpuBuilder {
+        self.input.extend(input.chars());
+        self
+    }
+
+    /// Sets whether `in` may read from stdin once the queued input runs out. If not,
+    /// the CPU halts with `Status::InputExhausted` instead.
+    pub fn read_stdin(mut self, read_stdin: bool) -> SynCpuBuilder {
+        self.read_stdin = read_stdin;
+        self
+    }
+
+    /// Collects everything written by `out` in `SynCpu::output`.
+    pub fn capture_output(mut self) -> SynCpuBuilder {
+        self.capture_output = true;
+        self
+    }
+
+    /// Stops the CPU printing its own messages, such as when a breakpoint is hit.
+    pub fn quiet(mut self) -> SynCpuBuilder {
+        self.loud = false;
+        self
+    }
+
+    /// Halts the CPU with `Status::StepLimitReached` after `max_steps` instructions.
+    pub fn max_steps(mut self, max_steps: u64) -> SynCpuBuilder {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Halts the CPU with `Status::StackOverflow` if a `push` or `call` would make the
+    /// stack deeper than `max_stack_depth`.
+    pub fn max_stack_depth(mut self, max_stack_depth: usize) -> SynCpuBuilder {
+        self.max_stack_depth = Some(max_stack_depth);
+        self
+    }
+
+    /// Applies `injections` to the CPU's data in order. They are kept by `SynCpu::reset`.
+    pub fn injections(mut self, injections: &[Injection]) -> SynCpuBuilder {
+        for injection in injections {
+            if self.error.is_some() {
+                break;
+            }
+            if let Err(e) = injection.inject(&mut self.data) {
+                self.error = Some(e);
+            }
+        }
+        self
+    }
+
+    /// Creates the CPU, or returns the error from the first injection that couldn't
+    /// be applied.
+    pub fn build(self) -> Result<SynCpu, InjectionError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let mut cpu = SynCpu::new(self.data);
+        cpu.stdin_buf = self.input;
+        cpu.read_stdin = self.read_stdin;
+        if self.capture_output {
+            cpu.output = Some(String::new());
+        }
+        cpu.loud = self.loud;
+        cpu.max_steps = self.max_steps;
+        cpu.max_stack_depth = self.max_stack_depth;
+        Ok(cpu)
+    }
+}
@@ -1,19 +1,37 @@
 //! CPU Memory and registers structure
 
+use std::convert::TryFrom;
 use std::io;
 use std::io::Cursor;
 use std::ops::{Index, IndexMut, RangeFrom};
 
+use cpu::instruction::{Instruction, Operation};
 use cpu::syn_int::SynInt;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use serde_json;
 
 /// The size of RAM for 15-bit addressing.
 /// Also the modular basis for all integer arithmetic
 pub const RAM_SIZE: usize = 32768;
 
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Mixes the little-endian bytes of a word into an FNV-1a hash.
+pub(crate) fn fnv_word(hash: u64, word: u16) -> u64 {
+    let [lo, hi] = word.to_le_bytes();
+    let hash = (hash ^ lo as u64).wrapping_mul(FNV_PRIME);
+    (hash ^ hi as u64).wrapping_mul(FNV_PRIME)
+}
+
+/// Returns true if the word is a printable ASCII character or a newline.
+pub fn is_printable(word: u16) -> bool {
+    (0x20..0x7f).contains(&word) || word == '\n' as u16
+}
+
 /// The data structures accessible on this architecture.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Data {
     /// Eight 16-bit registers
     pub registers: [u16; 8],
@@ -26,22 +44,42 @@ pub struct Data {
 impl Data {
     /// Constructs a new CPU data structure given a program binary.
     pub fn from_bin(binary: &[u8]) -> io::Result<Data> {
-        let mut data = Data {
-            registers: [0; 8],
-            ram: vec![0; RAM_SIZE],
-            stack: Vec::new(),
-        };
-
         let bin_len = binary.len();
         let mut rdr = Cursor::new(binary);
-        let mut idx = 0;
+        let mut words = Vec::with_capacity(bin_len / 2);
 
         while rdr.position() != bin_len as u64 {
-            data.ram[idx] = rdr.read_u16::<LittleEndian>()?;
-            idx += 1;
+            words.push(rdr.read_u16::<LittleEndian>()?);
         }
         
-        Ok(data)
+        Data::from_words(&words)
+    }
+
+    /// Constructs a new CPU data structure with `words` loaded at address 0, such as
+    /// the output of the assembler. Fails if there are more words than fit in RAM.
+    pub fn from_words(words: &[u16]) -> io::Result<Data> {
+        if words.len() > RAM_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{} words don't fit in RAM", words.len())));
+        }
+
+        let mut ram = vec![0; RAM_SIZE];
+        ram[..words.len()].copy_from_slice(words);
+        Ok(Data {
+            registers: [0; 8],
+            ram,
+            stack: Vec::new(),
+        })
+    }
+
+    /// Serializes the registers, stack and RAM as a JSON object.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserializes data written by `to_json`. The length of RAM isn't checked.
+    pub fn from_json(json: &str) -> serde_json::Result<Data> {
+        serde_json::from_str(json)
     }
 
     /// Pops a value from the stack. Panics if the stack is empty
@@ -59,6 +97,97 @@ impl Data {
         self.stack.is_empty()
     }
 
+    /// Returns the value on top of the stack without popping it, or `None` if the
+    /// stack is empty.
+    pub fn peek(&self) -> Option<u16> {
+        self.stack.last().cloned()
+    }
+
+    /// Returns the number of values on the stack.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns an iterator decoding every instruction in RAM from address 0.
+    pub fn instructions(&self) -> InstructionIter<'_> {
+        self.instructions_from(0)
+    }
+
+    /// Returns an iterator decoding the instructions in RAM from `start` onwards.
+    pub fn instructions_from(&self, start: u16) -> InstructionIter<'_> {
+        InstructionIter {
+            ram: &self.ram,
+            addr: start as usize,
+        }
+    }
+
+    /// Returns every run of at least `min_len` printable words in RAM, as defined by
+    /// `is_printable`, with the address it starts at.
+    pub fn strings(&self, min_len: usize) -> Vec<(u16, String)> {
+        let mut strings = Vec::new();
+        let mut start = 0;
+        while start < self.ram.len() {
+            let len = self.ram[start..].iter().take_while(|&&word| is_printable(word)).count();
+            if len > 0 && len >= min_len {
+                let text = self.ram[start..start + len].iter().map(|&word| word as u8 as char).collect();
+                strings.push((start as u16, text));
+            }
+            start += len + 1;
+        }
+        strings
+    }
+
+    /// Reads the RAM word at `addr`, or returns `None` if it's out of range.
+    ///
+    /// Unlike indexing, this doesn't wrap addresses around the end of RAM.
+    pub fn get(&self, addr: u16) -> Option<u16> {
+        self.ram.get(addr as usize).cloned()
+    }
+
+    /// Returns a mutable reference to the RAM word at `addr`, or `None` if it's out of range.
+    pub fn get_mut(&mut self, addr: u16) -> Option<&mut u16> {
+        self.ram.get_mut(addr as usize)
+    }
+
+    /// Lists every RAM word that differs between `self` and `other` as
+    /// `(address, self_value, other_value)`.
+    pub fn diff(&self, other: &Data) -> Vec<(u16, u16, u16)> {
+        self.ram.iter()
+            .zip(other.ram.iter())
+            .enumerate()
+            .filter(|&(_, (a, b))| a != b)
+            .map(|(addr, (&a, &b))| (addr as u16, a, b))
+            .collect()
+    }
+
+    /// Lists every register that differs between `self` and `other` as
+    /// `(register, self_value, other_value)`.
+    pub fn diff_registers(&self, other: &Data) -> Vec<(usize, u16, u16)> {
+        self.registers.iter()
+            .zip(other.registers.iter())
+            .enumerate()
+            .filter(|&(_, (a, b))| a != b)
+            .map(|(reg, (&a, &b))| (reg, a, b))
+            .collect()
+    }
+
+    /// Checks whether the stacks of `self` and `other` hold different values.
+    pub fn stack_differs(&self, other: &Data) -> bool {
+        self.stack != other.stack
+    }
+
+    /// Returns a cheap FNV-1a hash of the registers, RAM and stack.
+    ///
+    /// This is intended for detecting identical states, for example when looking for
+    /// loops, and is not suitable for any cryptographic use.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        for word in self.registers.iter().chain(self.ram.iter()).chain(self.stack.iter()) {
+            hash = fnv_word(hash, *word);
+        }
+        hash
+    }
+
     /// Convert a SynInt to a u16 either directly or
     /// by accessing a register.
     pub fn val(&self, idx: SynInt) -> u16 {
@@ -73,9 +202,9 @@ impl<'a> Index<SynInt> for Data {
     type Output = u16;
 
     fn index(&self, idx: SynInt) -> &u16 {
-        match idx {
-            SynInt::Literal(_) => panic!("Attempted to read a register with a literal"),
-            SynInt::Register(r) => self.registers.index(r),
+        match idx.register_index() {
+            Some(r) => self.registers.index(r),
+            None => panic!("Attempted to read a register with a literal"),
         }
     }
 }
@@ -105,9 +234,9 @@ impl Index<RangeFrom<u16>> for Data {
 impl IndexMut<SynInt> for Data {
 
     fn index_mut(&mut self, idx: SynInt) -> &mut u16 {
-        match idx {
-            SynInt::Literal(_) => panic!("Attempted to write to a register using a literal."),
-            SynInt::Register(r) => self.registers.index_mut(r),
+        match idx.register_index() {
+            Some(r) => self.registers.index_mut(r),
+            None => panic!("Attempted to write to a register using a literal."),
         }
     }
 }
@@ -123,4 +252,31 @@ impl IndexMut<usize> for Data {
     }
 }
 
+impl<'a> TryFrom<&'a [u8]> for Data {
+    type Error = io::Error;
 
+    fn try_from(binary: &'a [u8]) -> io::Result<Data> {
+        Data::from_bin(binary)
+    }
+}
+
+/// An iterator over the instructions in RAM, yielding each with its address.
+///
+/// Words that don't decode, including an instruction cut short by the end of RAM,
+/// are yielded one at a time as `Instruction::_Unknown`.
+pub struct InstructionIter<'a> {
+    ram: &'a [u16],
+    addr: usize,
+}
+
+impl<'a> Iterator for InstructionIter<'a> {
+    type Item = (u16, Instruction);
+
+    fn next(&mut self) -> Option<(u16, Instruction)> {
+        let words = self.ram.get(self.addr..).filter(|words| !words.is_empty())?;
+        let instr = Operation::next(words).instr();
+        let addr = self.addr as u16;
+        self.addr += instr.word_size() as usize;
+        Some((addr, instr))
+    }
+}
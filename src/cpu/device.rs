@@ -0,0 +1,18 @@
+//! Memory mapped devices
+//!
+//! Devices can claim RAM addresses so that `rmem` and `wmem` call out to them
+//! instead of touching RAM, for example to map a timer or a logging region onto
+//! the architecture without changing the VM itself.
+
+/// A device consulted by `rmem` and `wmem` before RAM.
+///
+/// Devices are asked in the order they were added to the CPU, and the first to
+/// claim an address handles the access.
+pub trait MemoryDevice {
+    /// Returns the value read from `addr`, or `None` if the device doesn't map `addr`.
+    fn read(&mut self, addr: u16) -> Option<u16>;
+
+    /// Handles a write of `val` to `addr`, returning false if the device doesn't
+    /// map `addr`.
+    fn write(&mut self, addr: u16, val: u16) -> bool;
+}
@@ -0,0 +1,250 @@
+//! Table driven dispatch
+//!
+//! An alternative to `SynCpu::step` for the hot loop. Each opcode indexes a table
+//! of handlers that read their operands straight from RAM instead of decoding
+//! them into an `Instruction` first. Anything a handler can't evaluate exactly as
+//! `step` would, such as I/O, faults, watched writes or memory mapped devices, is
+//! left to `step`, as is everything while tracing or coverage is enabled. A stack
+//! at its depth limit counts as a fault.
+
+use cpu::SynCpu;
+use cpu::data::RAM_SIZE;
+use cpu::syn_int::MOD_BASE;
+
+/// Evaluates an instruction given its operand words, returning false without
+/// changing any state if it must be left to `step`.
+type Handler = fn(&mut SynCpu, u16, u16, u16) -> bool;
+
+/// The handler for each opcode, or `None` for opcodes always left to `step`.
+const TABLE: [Option<Handler>; 22] = [
+    None,           // halt
+    Some(set),
+    Some(push),
+    Some(pop),
+    Some(eq),
+    Some(gt),
+    Some(jmp),
+    Some(jt),
+    Some(jf),
+    Some(add),
+    Some(mult),
+    Some(modulo),
+    Some(and),
+    Some(or),
+    Some(not),
+    Some(rmem),
+    Some(wmem),
+    Some(call),
+    Some(ret),
+    None,           // out
+    None,           // in
+    Some(noop),
+];
+
+/// Returns the value of an operand, or `None` if it isn't a valid literal or register.
+fn val(cpu: &SynCpu, word: u16) -> Option<u16> {
+    match word {
+        0..=32767 => Some(word),
+        32768..=32775 => Some(cpu.data.registers[(word - 32768) as usize]),
+        _ => None,
+    }
+}
+
+/// Returns the register an operand names, or `None` if it isn't a register.
+fn reg(word: u16) -> Option<usize> {
+    match word {
+        32768..=32775 => Some((word - 32768) as usize),
+        _ => None,
+    }
+}
+
+/// Stores `f(a, b)` in register `dst`.
+fn binary<F: Fn(u16, u16) -> u16>(cpu: &mut SynCpu, dst: u16, a: u16, b: u16, f: F) -> bool {
+    match (reg(dst), val(cpu, a), val(cpu, b)) {
+        (Some(dst), Some(a), Some(b)) => {
+            cpu.data.registers[dst] = f(a, b);
+            cpu.pc += 4;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn set(cpu: &mut SynCpu, dst: u16, a: u16, _: u16) -> bool {
+    match (reg(dst), val(cpu, a)) {
+        (Some(dst), Some(a)) => {
+            cpu.data.registers[dst] = a;
+            cpu.pc += 3;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn push(cpu: &mut SynCpu, a: u16, _: u16, _: u16) -> bool {
+    match val(cpu, a) {
+        Some(a) if !cpu.stack_full() => {
+            cpu.data.push(a);
+            cpu.pc += 2;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn pop(cpu: &mut SynCpu, dst: u16, _: u16, _: u16) -> bool {
+    match (reg(dst), cpu.data.peek()) {
+        (Some(dst), Some(top)) => {
+            cpu.data.pop();
+            cpu.data.registers[dst] = top;
+            cpu.pc += 2;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn eq(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| (a == b) as u16)
+}
+
+fn gt(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| (a > b) as u16)
+}
+
+fn jmp(cpu: &mut SynCpu, a: u16, _: u16, _: u16) -> bool {
+    match val(cpu, a) {
+        Some(a) => {
+            cpu.pc = a;
+            true
+        },
+        None => false,
+    }
+}
+
+fn jt(cpu: &mut SynCpu, a: u16, b: u16, _: u16) -> bool {
+    match (val(cpu, a), val(cpu, b)) {
+        (Some(a), Some(b)) => {
+            cpu.pc = if a != 0 { b } else { cpu.pc + 3 };
+            true
+        },
+        _ => false,
+    }
+}
+
+fn jf(cpu: &mut SynCpu, a: u16, b: u16, _: u16) -> bool {
+    match (val(cpu, a), val(cpu, b)) {
+        (Some(a), Some(b)) => {
+            cpu.pc = if a == 0 { b } else { cpu.pc + 3 };
+            true
+        },
+        _ => false,
+    }
+}
+
+fn add(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| ((a as u32 + b as u32) % MOD_BASE) as u16)
+}
+
+fn mult(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| ((a as u32 * b as u32) % MOD_BASE) as u16)
+}
+
+fn modulo(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    // Leave division by zero for `step` to fault on
+    if val(cpu, b) == Some(0) {
+        return false;
+    }
+    binary(cpu, dst, a, b, |a, b| a % b)
+}
+
+fn and(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| a & b)
+}
+
+fn or(cpu: &mut SynCpu, dst: u16, a: u16, b: u16) -> bool {
+    binary(cpu, dst, a, b, |a, b| a | b)
+}
+
+fn not(cpu: &mut SynCpu, dst: u16, a: u16, _: u16) -> bool {
+    match (reg(dst), val(cpu, a)) {
+        (Some(dst), Some(a)) => {
+            cpu.data.registers[dst] = 0b111111111111111 ^ a;
+            cpu.pc += 3;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn rmem(cpu: &mut SynCpu, dst: u16, a: u16, _: u16) -> bool {
+    match (reg(dst), val(cpu, a)) {
+        (Some(dst), Some(addr)) if cpu.devices.is_empty() => {
+            cpu.data.registers[dst] = cpu.data[addr];
+            cpu.pc += 3;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn wmem(cpu: &mut SynCpu, a: u16, b: u16, _: u16) -> bool {
+    // Registers can hold addresses past the end of RAM, which wrap around as in `step`
+    match (val(cpu, a).map(|addr| (addr as usize % RAM_SIZE) as u16), val(cpu, b)) {
+        (Some(addr), Some(b)) if !cpu.watchpoints.contains(&addr) && !cpu.is_protected(addr)
+                                   && cpu.devices.is_empty() => {
+            cpu.data[addr] = b;
+            cpu.pc += 3;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn call(cpu: &mut SynCpu, a: u16, _: u16, _: u16) -> bool {
+    match val(cpu, a) {
+        Some(a) if !cpu.stack_full() => {
+            cpu.call_stack.push(cpu.pc);
+            cpu.data.push(cpu.pc + 2);
+            cpu.pc = a;
+            true
+        },
+        _ => false,
+    }
+}
+
+fn ret(cpu: &mut SynCpu, _: u16, _: u16, _: u16) -> bool {
+    // Halting, and returning without a matching call, are left to `step`
+    if cpu.data.is_stack_empty() || cpu.call_stack.is_empty() {
+        return false;
+    }
+    cpu.call_stack.pop();
+    cpu.pc = cpu.data.pop();
+    true
+}
+
+fn noop(cpu: &mut SynCpu, _: u16, _: u16, _: u16) -> bool {
+    cpu.pc += 1;
+    true
+}
+
+impl SynCpu {
+    /// Evaluates the next instruction exactly as `step` does, using a dispatch
+    /// table indexed by opcode where possible.
+    pub fn step_dispatch(&mut self) {
+        let pc = self.pc as usize;
+        if self.trace.is_none() && self.coverage.is_none() && pc + 4 <= RAM_SIZE {
+            let words = &self.data.ram[pc..pc + 4];
+            let (op, a, b, c) = (words[0], words[1], words[2], words[3]);
+
+            if let Some(&Some(handler)) = TABLE.get(op as usize) {
+                self.watch_hit = None;
+                if handler(self, a, b, c) {
+                    self.finish_step(Some(op));
+                    return;
+                }
+            }
+        }
+        self.step();
+    }
+}
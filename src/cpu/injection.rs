@@ -2,40 +2,177 @@
 //!
 //! This module
 
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
 use serde_json;
 use cpu::Data;
+use cpu::data::RAM_SIZE;
+use cpu::assembler::{self, AssembleError};
+
+/// An error preventing an injection from being applied.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InjectionError {
+    /// The payload starting at the given address, of the given length, runs past the end of RAM
+    OutOfBounds(u16, usize),
+    /// The register index is not between 0 and 7
+    InvalidRegister(usize),
+}
+
+impl fmt::Display for InjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::InjectionError::*;
+        match *self {
+            OutOfBounds(addr, len) => write!(f, "a payload of {} words at 0x{:0>4x} runs past the end of RAM",
+                                             len, addr),
+            InvalidRegister(reg) => write!(f, "register r{} does not exist", reg),
+        }
+    }
+}
+
+impl error::Error for InjectionError {
+    fn description(&self) -> &str {
+        use self::InjectionError::*;
+        match *self {
+            OutOfBounds(..) => "Payload out of bounds",
+            InvalidRegister(..) => "Invalid register",
+        }
+    }
+}
 
 /// A struct for injecting arbitrary data into a binary
+///
+/// Every field is optional in the JSON form, so an injection may patch RAM,
+/// preset registers, seed the stack, or any combination of these. The RAM
+/// patch can be given either as raw `payload` words or as `asm` source.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Injection {
     /// The memory address to start injecting at
+    #[serde(default)]
     addr: u16,
     /// The sequence of words to inject
+    #[serde(default)]
     payload: Vec<u16>,
+    /// Assembly source for the payload, used instead of `payload` in the JSON form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asm: Option<String>,
+    /// Pairs of register index and the value to set it to
+    #[serde(default)]
+    registers: Vec<(usize, u16)>,
+    /// Values pushed onto the stack, bottom first
+    #[serde(default)]
+    stack: Vec<u16>,
 }
 
 
 
+/// The values an injection replaced, used to revert it.
+#[derive(Debug, Clone)]
+pub struct Overwritten {
+    /// The previous contents of the RAM the payload was written over
+    ram: Vec<u16>,
+    /// The previous values of each register that was set
+    registers: Vec<(usize, u16)>,
+}
+
 impl Injection {
 
+    /// Creates an injection writing the assembled `source` to RAM at `addr`.
+    pub fn from_asm(addr: u16, source: &str) -> Result<Injection, AssembleError> {
+        Ok(Injection {
+            addr,
+            payload: assembler::assemble(source)?,
+            asm: None,
+            registers: Vec::new(),
+            stack: Vec::new(),
+        })
+    }
+
     /// Creates a vector of injections from a json string
     pub fn from_json(json: &str) -> Vec<Injection> {
-        match serde_json::from_str(json) {
+        let mut injections: Vec<Injection> = match serde_json::from_str(json) {
             Ok(vec) => vec,
             Err(e) => {
                 println!("Deserialization error: {}", e);
-                vec![]
+                return vec![];
+            }
+        };
+
+        for injection in injections.iter_mut() {
+            if let Some(source) = injection.asm.take() {
+                if !injection.payload.is_empty() {
+                    println!("Injection at 0x{:0>4x} has both a payload and asm source", injection.addr);
+                    return vec![];
+                }
+                match assembler::assemble(&source) {
+                    Ok(payload) => injection.payload = payload,
+                    Err(e) => {
+                        println!("Assembly error in injection at 0x{:0>4x}: {}", injection.addr, e);
+                        return vec![];
+                    }
+                }
             }
         }
+        injections
     }
 
-    /// Inject the payload
-    pub fn inject(&self, data: &mut Data) {
-        let mut pc = self.addr;
+    /// Inject the payload, returning the range of RAM addresses that were written.
+    ///
+    /// Nothing is written if the payload would run past the end of RAM or a
+    /// register doesn't exist.
+    pub fn inject(&self, data: &mut Data) -> Result<Range<u16>, InjectionError> {
+        let start = self.addr as usize;
+        let end = start + self.payload.len();
+        if end > RAM_SIZE {
+            return Err(InjectionError::OutOfBounds(self.addr, self.payload.len()));
+        }
+        if let Some(&(reg, _)) = self.registers.iter().find(|&&(reg, _)| reg >= data.registers.len()) {
+            return Err(InjectionError::InvalidRegister(reg));
+        }
+
+        data.ram[start..end].copy_from_slice(&self.payload);
 
-        for i in self.payload.iter() {
-            data.ram[pc as usize] = *i;
-            pc += 1;
+        for &(reg, val) in self.registers.iter() {
+            data.registers[reg] = val;
         }
+
+        for val in self.stack.iter() {
+            data.push(*val);
+        }
+
+        Ok(self.addr..end as u16)
+    }
+
+    /// Inject the payload as `inject` does, also returning the values it overwrote
+    /// so that it can later be undone with `revert`.
+    pub fn inject_saved(&self, data: &mut Data) -> Result<(Range<u16>, Overwritten), InjectionError> {
+        let start = self.addr as usize;
+        let saved = Overwritten {
+            ram: data.ram.get(start..start + self.payload.len())
+                .map(|words| words.to_vec())
+                .unwrap_or_default(),
+            registers: self.registers.iter()
+                .filter_map(|&(reg, _)| data.registers.get(reg).map(|&val| (reg, val)))
+                .collect(),
+        };
+
+        let range = self.inject(data)?;
+        Ok((range, saved))
+    }
+
+    /// Undo an injection applied by `inject_saved`, restoring the overwritten RAM and
+    /// registers and popping the values it pushed onto the stack.
+    pub fn revert(&self, data: &mut Data, saved: &Overwritten) {
+        let start = self.addr as usize;
+        data.ram[start..start + saved.ram.len()].copy_from_slice(&saved.ram);
+
+        // Restore in reverse so a register set twice gets its original value back
+        for &(reg, val) in saved.registers.iter().rev() {
+            data.registers[reg] = val;
+        }
+
+        let len = data.stack.len().saturating_sub(self.stack.len());
+        data.stack.truncate(len);
     }
 }
@@ -23,15 +23,54 @@
 //! | `ReadMem` | `15` | `rmem dst src` | Read memory at address `src` and write it to `dst` |
 //! | `WriteMem` | `16` | `wmem dst src` | Write the value from `src` into memory at address `src`. |
 //! | `Call` | `17` | `call dst` | Write the address of the next instruction to the stack and jump to `dst`.
-//! | `Ret` | `18` | `ret` | Remove the top element from the stack and jump to it, halt on an empty stack. |
+//! | `Ret` | `18` | `ret` | Remove the top element from the stack and jump to it, halt with `Status::ReturnUnderflow` on an empty stack. |
 //! | `Out` | `19` | `out val` | Write the ASCII character code `val` to the terminal. |
 //! | `In` | `20` | `in dst` | Read a character from the terminal and write its ASCII code to `dst`. |
 //! | `Noop` | `21` | `noop` | No operation. |
+//!
+//! Instructions can also be serialized with serde, and `to_json` and `from_json`
+//! convert a whole programme to and from a JSON array for use by other tools.
 
 use cpu::syn_int::SynInt;
 
+use serde::de::Error;
+use serde_json;
+
 use std::fmt;
 use std::char;
+use std::convert::TryFrom;
+use std::error;
+
+/// The nominal cost of each opcode in cycles, indexed by opcode. The figures are only
+/// meant for comparing code paths: arithmetic beyond addition, memory access, calls,
+/// returns and I/O cost more than simple register operations.
+pub const OPCODE_COSTS: [u32; 22] = [
+    1, // halt
+    1, // set
+    2, // push
+    2, // pop
+    1, // eq
+    1, // gt
+    1, // jmp
+    1, // jt
+    1, // jf
+    1, // add
+    3, // mult
+    4, // mod
+    1, // and
+    1, // or
+    1, // not
+    3, // rmem
+    3, // wmem
+    3, // call
+    3, // ret
+    2, // out
+    2, // in
+    1, // noop
+];
+
+/// The cost in cycles of an unknown instruction.
+pub const UNKNOWN_COST: u32 = 1;
 
 /// Enum representation of all the supported instructions.
 ///
@@ -39,7 +78,7 @@ use std::char;
 /// documentation for more infromation about what these operations do and what
 /// the arguments mean.
 #[allow(missing_docs)]
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 pub enum Instruction {
     Halt,
     Set(SynInt, SynInt),
@@ -63,39 +102,164 @@ pub enum Instruction {
     Out(SynInt),
     In(SynInt),
     Noop,
+    /// Not a real instruction, so serializing one is an error
     #[doc(hidden)]
+    #[serde(skip_serializing, skip_deserializing)]
     _Unknown,
 }
 
 impl Instruction {
-    /// Returns the amount to increment the program counter by after
-    /// executing the instruction. Note that this returns 0 for all jump
-    /// instructions as they modify the program counter directly.
+    /// Decodes the instruction at the start of `words`, reporting an unknown opcode
+    /// or an instruction cut short by the end of the slice.
+    pub fn try_from_words(words: &[u16]) -> Result<Instruction, DecodeError> {
+        use self::Instruction::*;
+
+        let opcode = match words.first() {
+            Some(&opcode) => opcode,
+            None => return Err(DecodeError::Empty),
+        };
+
+        // Copy into a padded buffer so that a truncated instruction can be
+        // decoded far enough to know how long it should be.
+        let mut w = [0; 4];
+        for (word, val) in w.iter_mut().zip(words) {
+            *word = *val;
+        }
+
+        let instr = match opcode {
+            0 => Halt,
+            1 => Set(w[1].into(), w[2].into()),
+            2 => Push(w[1].into()),
+            3 => Pop(w[1].into()),
+            4 => Eq(w[1].into(), w[2].into(), w[3].into()),
+            5 => Gt(w[1].into(), w[2].into(), w[3].into()),
+            6 => Jmp(w[1].into()),
+            7 => Jt(w[1].into(), w[2].into()),
+            8 => Jf(w[1].into(), w[2].into()),
+            9 => Add(w[1].into(), w[2].into(), w[3].into()),
+            10 => Mult(w[1].into(), w[2].into(), w[3].into()),
+            11 => Mod(w[1].into(), w[2].into(), w[3].into()),
+            12 => And(w[1].into(), w[2].into(), w[3].into()),
+            13 => Or(w[1].into(), w[2].into(), w[3].into()),
+            14 => Not(w[1].into(), w[2].into()),
+            15 => ReadMem(w[1].into(), w[2].into()),
+            16 => WriteMem(w[1].into(), w[2].into()),
+            17 => Call(w[1].into()),
+            18 => Ret,
+            19 => Out(w[1].into()),
+            20 => In(w[1].into()),
+            21 => Noop,
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
+        };
+
+        let len = instr.word_size() as usize;
+        if len > words.len() {
+            return Err(DecodeError::Truncated(opcode, len - words.len()));
+        }
+        Ok(instr)
+    }
+
+    /// Returns the amount `SynCpu::step` increments the program counter by after
+    /// executing the instruction. This is 0 for halting and for every jump, call and
+    /// return, as they set the program counter directly.
+    ///
+    /// Use `word_size` to find where the next instruction in RAM starts.
     pub fn size(&self) -> u16 {
         use self::Instruction::*;
 
         match *self {
-            // Jumps will increment the pc manually
             Halt | Jmp(_) | Jt(_, _) | Jf(_, _) | Call(_) | Ret => 0,
-            Noop | _Unknown => 1,
-            Push(_) | Pop(_) | Out(_) | In(_) => 2,
-            Set(_, _) | Not(_, _) | ReadMem(_, _) | WriteMem(_, _) => 3,
-            _ => 4,
+            _ => self.word_size(),
         }
     }
 
-    /// Returns the size of the instruction in word counts.
+    /// Returns the number of words the instruction occupies in RAM, including its
+    /// operands, for every instruction. Unlike `size`, jumps aren't special cased.
     pub fn word_size(&self) -> u16 {
         use self::Instruction::*;
         
         match *self {
-            // Jumps will increment the pc manually
             Halt | Ret | Noop | _Unknown => 1,
             Push(_) | Pop(_) | Out(_) | In(_) | Jmp(_) | Call(_) => 2,
             Set(_, _) | Not(_, _) | ReadMem(_, _) | WriteMem(_, _) | Jt(_, _) | Jf (_, _) => 3,
             _ => 4,
         }
     }
+
+    /// Returns the literal address a jump or call may transfer control to, if any.
+    /// Targets held in registers can't be known without running the programme.
+    pub fn jump_target(&self) -> Option<u16> {
+        use self::Instruction::*;
+
+        match *self {
+            Jmp(dst) | Call(dst) | Jt(_, dst) | Jf(_, dst) => dst.as_literal(),
+            _ => None,
+        }
+    }
+
+    /// Returns the nominal cost of evaluating the instruction in cycles, from `OPCODE_COSTS`.
+    pub fn cost(&self) -> u32 {
+        self.opcode().map_or(UNKNOWN_COST, |op| OPCODE_COSTS[op as usize])
+    }
+
+    /// Returns the opcode of the instruction, or `None` for an unknown instruction.
+    pub fn opcode(&self) -> Option<u16> {
+        use self::Instruction::*;
+
+        let op = match *self {
+            Halt => 0,
+            Set(_, _) => 1,
+            Push(_) => 2,
+            Pop(_) => 3,
+            Eq(_, _, _) => 4,
+            Gt(_, _, _) => 5,
+            Jmp(_) => 6,
+            Jt(_, _) => 7,
+            Jf(_, _) => 8,
+            Add(_, _, _) => 9,
+            Mult(_, _, _) => 10,
+            Mod(_, _, _) => 11,
+            And(_, _, _) => 12,
+            Or(_, _, _) => 13,
+            Not(_, _) => 14,
+            ReadMem(_, _) => 15,
+            WriteMem(_, _) => 16,
+            Call(_) => 17,
+            Ret => 18,
+            Out(_) => 19,
+            In(_) => 20,
+            Noop => 21,
+            _Unknown => return None,
+        };
+        Some(op)
+    }
+
+    /// Returns the operands of the instruction in the order they are stored in RAM.
+    pub fn operands(&self) -> Vec<SynInt> {
+        use self::Instruction::*;
+
+        match *self {
+            Halt | Ret | Noop | _Unknown => vec![],
+            Push(a) | Pop(a) | Jmp(a) | Call(a) | Out(a) | In(a) => vec![a],
+            Set(a, b) | Jt(a, b) | Jf(a, b) | Not(a, b) | ReadMem(a, b) | WriteMem(a, b) => vec![a, b],
+            Eq(a, b, c) | Gt(a, b, c) | Add(a, b, c) | Mult(a, b, c) | Mod(a, b, c)
+                | And(a, b, c) | Or(a, b, c) => vec![a, b, c],
+        }
+    }
+
+    /// Encodes the instruction as the sequence of words that `Operation::next`
+    /// would decode it from: the opcode followed by each operand. An unknown
+    /// instruction has no encoding and produces no words.
+    pub fn encode(&self) -> Vec<u16> {
+        match self.opcode() {
+            Some(op) => {
+                let mut words = vec![op];
+                words.extend(self.operands().into_iter().map(u16::from));
+                words
+            },
+            None => vec![],
+        }
+    }
 }
 
 impl fmt::Display for Instruction {
@@ -140,9 +304,14 @@ impl fmt::Display for Instruction {
             Ret                => write!(f, "ret  "),
             Out(val)           => {
                 match val {
+                    // Only printable ASCII is quoted, so every line reads back in
+                    SynInt::Literal(x) if (0x20..0x7f).contains(&x) => {
+                        write!(f, "out  '{}'",
+                               x as u8 as char)
+                    },
                     SynInt::Literal(x) => {
-                        write!(f, "out  '{:#}'",
-                               char::from_u32(x as u32).unwrap())
+                        write!(f, "out  {}",
+                               x)
                     },
                     SynInt::Register(r) => {
                         write!(f, "out  r{}",
@@ -158,14 +327,55 @@ impl fmt::Display for Instruction {
     }
 }
 
+impl<'a> TryFrom<&'a [u16]> for Instruction {
+    type Error = DecodeError;
+
+    fn try_from(words: &'a [u16]) -> Result<Instruction, DecodeError> {
+        Instruction::try_from_words(words)
+    }
+}
+
+/// An error returned when words can't be decoded as an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeError {
+    /// There were no words to decode
+    Empty,
+    /// The opcode isn't one of the 22 instructions
+    UnknownOpcode(u16),
+    /// The words ended before the operands of the given opcode, with this many missing
+    Truncated(u16, usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DecodeError::*;
+        match *self {
+            Empty => write!(f, "no words to decode"),
+            UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            Truncated(op, 1) => write!(f, "opcode {} is missing 1 operand", op),
+            Truncated(op, missing) => write!(f, "opcode {} is missing {} operands", op, missing),
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        use self::DecodeError::*;
+        match *self {
+            Empty => "No words to decode",
+            UnknownOpcode(..) => "Unknown opcode",
+            Truncated(..) => "Truncated instruction",
+        }
+    }
+}
+
 /// A VM extension to support interrupt instructions.
 ///
 /// Interrupt symbols are used so the debugger can pause execution
-/// and inspect the VM. They are implemented using the upper byte
-/// of a 16-bit instruction. If the upper byte is equal to `0xcc`
-/// (the `int 3` opcode in x86) then the instruction is a breakpoint,
-/// otherwise it is a regular instruction.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// and inspect the VM. Breakpoints are stored in a side table on the
+/// `SynCpu` rather than in RAM, so decoding RAM directly always yields
+/// a regular instruction and `SynCpu::peek_op_at` marks breakpoints.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum Operation {
     /// A regular instruction
     Regular(Instruction),
@@ -176,40 +386,11 @@ pub enum Operation {
 
 impl Operation {
 
-    /// Returns the next operation from RAM.
+    /// Returns the next operation from RAM. If the instruction can't be decoded,
+    /// including when the slice is too short to hold all of it, it is unknown.
+    /// `Instruction::try_from_words` reports why.
     pub fn next(ram: &[u16]) -> Operation {
-        use self::Instruction::*;
-        let instr = match 0x00ff & ram[0] {
-            0 => Halt,
-            1 => Set(ram[1].into(), ram[2].into()),
-            2 => Push(ram[1].into()),
-            3 => Pop(ram[1].into()),
-            4 => Eq(ram[1].into(), ram[2].into(), ram[3].into()),
-            5 => Gt(ram[1].into(), ram[2].into(), ram[3].into()),
-            6 => Jmp(ram[1].into()),
-            7 => Jt(ram[1].into(), ram[2].into()),
-            8 => Jf(ram[1].into(), ram[2].into()),
-            9 => Add(ram[1].into(), ram[2].into(), ram[3].into()),
-            10 => Mult(ram[1].into(), ram[2].into(), ram[3].into()),
-            11 => Mod(ram[1].into(), ram[2].into(), ram[3].into()),
-            12 => And(ram[1].into(), ram[2].into(), ram[3].into()),
-            13 => Or(ram[1].into(), ram[2].into(), ram[3].into()),
-            14 => Not(ram[1].into(), ram[2].into()),
-            15 => ReadMem(ram[1].into(), ram[2].into()),
-            16 => WriteMem(ram[1].into(), ram[2].into()),
-            17 => Call(ram[1].into()),
-            18 => Ret,
-            19 => Out(ram[1].into()),
-            20 => In(ram[1].into()),
-            21 => Noop,
-            _ => _Unknown,
-        };
-
-        if (ram[0] >> 8) & 0xcc == 0xcc {
-            Operation::Breakpoint(instr)
-        } else {
-            Operation::Regular(instr)
-        }
+        Operation::Regular(Instruction::try_from_words(ram).unwrap_or(Instruction::_Unknown))
     }
 
     /// Unwraps the operation to produce an instruction.
@@ -230,9 +411,10 @@ impl Operation {
 
     /// Checks if the address offset in ram is a valid instruction
     pub fn is_valid(offset: usize, ram: &[u16]) -> bool {
-        let instr = Operation::next(&ram[offset..]).instr();
-
-        instr != Instruction::_Unknown
+        match ram.get(offset..) {
+            Some(words) => Operation::next(words).instr() != Instruction::_Unknown,
+            None => false,
+        }
     }
 }
 
@@ -244,3 +426,25 @@ impl fmt::Display for Operation {
         }
     }
 }
+
+/// Serializes a programme as a JSON array of instructions. Fails if the programme
+/// contains `Instruction::_Unknown`.
+pub fn to_json(program: &[Instruction]) -> serde_json::Result<String> {
+    serde_json::to_string(program)
+}
+
+/// Deserializes a programme written by `to_json`, rejecting registers other than
+/// `r0`-`r7` and literals that don't fit in 15 bits.
+pub fn from_json(json: &str) -> serde_json::Result<Vec<Instruction>> {
+    let program: Vec<Instruction> = serde_json::from_str(json)?;
+    for (i, instr) in program.iter().enumerate() {
+        let valid = instr.operands().iter().all(|&operand| match operand {
+            SynInt::Literal(val) => val < 32768,
+            SynInt::Register(reg) => reg < 8,
+        });
+        if !valid {
+            return Err(serde_json::Error::custom(format!("instruction {} has an invalid operand: {:?}", i, instr)));
+        }
+    }
+    Ok(program)
+}
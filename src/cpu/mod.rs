@@ -2,26 +2,68 @@
 //!
 //! Emulates the SynCpu architecture.
 
+pub mod assembler;
+pub mod breakpoint;
+pub mod builder;
+pub mod device;
 pub mod injection;
 pub mod syn_int;
 pub mod data;
 pub mod status;
 pub mod instruction;
+pub mod runner;
+pub mod snapshot;
+pub mod stats;
+pub mod trace;
+mod dispatch;
 
 pub use self::data::Data;
 pub use self::status::Status;
-pub use self::instruction::{Operation, Instruction};
+pub use self::instruction::{Operation, Instruction, DecodeError};
 pub use self::injection::Injection;
+pub use self::breakpoint::Breakpoint;
+pub use self::builder::SynCpuBuilder;
+pub use self::device::MemoryDevice;
+pub use self::runner::run_program;
+pub use self::snapshot::Snapshot;
+use self::syn_int::SynInt;
 
+#[cfg(feature = "cli")]
 use chan;
+#[cfg(feature = "cli")]
 use chan_signal;
+#[cfg(feature = "cli")]
 use chan_signal::Signal;
 
+#[cfg(feature = "cli")]
+use termion;
+#[cfg(feature = "cli")]
 use termion::{color, style};
 
 use std::char;
-use std::io::{stdin, Read};
+use std::cmp;
+#[cfg(feature = "cli")]
+use std::env;
+use std::fmt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{stdin, stdout, Write};
+use std::ops::Range;
+#[cfg(feature = "cli")]
+use std::io::Read;
+#[cfg(feature = "cli")]
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "cli")]
+use std::sync::OnceLock;
+#[cfg(feature = "cli")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "cli")]
 use std::thread;
+#[cfg(feature = "cli")]
+use std::time::Duration;
+
+/// A callback given the pc, address, old value and new value of a write to code.
+pub type SelfModifyCallback = Arc<dyn Fn(u16, u16, u16, u16) + Send + Sync>;
 
 /// An emulator for the SynCpu architecture.
 #[derive(Clone)]
@@ -34,28 +76,376 @@ pub struct SynCpu {
     pub status: status::Status,
     /// The VM data
     pub data: Data,
-    /// A buffer for reads from stdin
-    pub stdin_buf: Vec<char>,
+    /// A buffer for reads from stdin. `in` only reads from stdin once this is empty.
+    pub stdin_buf: VecDeque<char>,
     /// Controls whether the CPU prints messages when it receives signals from the underlying
     /// operating system or not.
     pub loud: bool,
+    /// The addresses of the `call` instructions for each active subroutine, innermost last.
+    ///
+    /// This is tracked independently of the data stack so that return addresses can't be
+    /// confused with values pushed by the programme itself.
+    pub call_stack: Vec<u16>,
+    /// Addresses of instructions that pause execution before they are evaluated.
+    ///
+    /// These are kept out of RAM so that breakpoints never alter the programme's data.
+    pub breakpoints: HashMap<u16, Breakpoint>,
+    /// RAM addresses that pause execution when written to.
+    pub watchpoints: HashSet<u16>,
+    /// Opcodes that pause execution before any instruction with that opcode is evaluated.
+    pub trap_opcodes: HashSet<u16>,
+    /// The address, old value and new value of a watched write made by the last step, if any.
+    pub watch_hit: Option<(u16, u16, u16)>,
+    /// RAM ranges that `wmem` may not write to. A write inside one halts with
+    /// `Status::WriteProtectViolation` instead of changing RAM.
+    pub protected: Vec<Range<u16>>,
+    /// Devices consulted by `rmem` and `wmem` before RAM, in order.
+    ///
+    /// They are shared rather than copied when the CPU is cloned, and can be inspected
+    /// through the handle returned by `add_device`.
+    pub devices: Vec<Arc<Mutex<dyn MemoryDevice + Send>>>,
+    /// Every character consumed by `in`, in the order it was read, if recording is enabled.
+    ///
+    /// This includes characters taken from a replay buffer, so the log can be saved and
+    /// loaded as a replay file to reproduce the whole session.
+    pub record: Option<String>,
+    /// Characters written by `out`, if output is being captured rather than printed.
+    pub output: Option<String>,
+    /// Characters written by `out` that haven't been printed to stdout yet.
+    out_buf: String,
+    /// Whether characters written by `out` are printed to stdout. While it's off they're
+    /// kept buffered instead, and printed once it's turned back on and the output is
+    /// flushed. Output captured in `output` is unaffected. This is separate from `loud`,
+    /// which only covers the CPU's own messages.
+    pub echo: bool,
+    /// Whether `in` may block reading from stdin once `stdin_buf` is empty. If not, the
+    /// CPU halts with `Status::InputExhausted` instead.
+    pub read_stdin: bool,
+    /// The state before each evaluated instruction, if tracing has been enabled.
+    pub trace: Option<Vec<trace::TraceEntry>>,
+    /// Whether each RAM address has held part of an instruction evaluated by `step`,
+    /// if coverage has been enabled.
+    pub coverage: Option<Vec<bool>>,
+    /// Called with the pc, address, old value and new value whenever `wmem` writes to
+    /// an address marked in `coverage`, so that self-modifying code can be spotted.
+    pub on_self_modify: Option<SelfModifyCallback>,
+    /// The registers, RAM and stack when the CPU was created, restored by `reset`.
+    initial: Arc<Data>,
+    /// The states visited so far, if loop detection has been enabled.
+    loops: Option<LoopDetector>,
+    /// Counts of the instructions evaluated and the stack's high-water mark.
+    pub stats: stats::Stats,
+    /// The number of instructions, counted by `stats`, after which the CPU halts with
+    /// `Status::StepLimitReached`.
+    pub max_steps: Option<u64>,
+    /// The deepest the stack may grow. A `push` or `call` beyond it halts with
+    /// `Status::StackOverflow` instead. The stack is unbounded if this isn't set.
+    pub max_stack_depth: Option<usize>,
+}
+
+/// Fingerprints of the states the CPU has visited, sampled every `every` steps.
+///
+/// Sampling can only delay detection: once execution is in a cycle, the sampled
+/// states repeat within one pass of the cycle per step of the interval.
+#[derive(Clone)]
+struct LoopDetector {
+    every: u64,
+    steps: u64,
+    seen: HashSet<u64>,
+}
+
+/// Returns true if terminal colours and styles should be written: stdout is a terminal
+/// and the `NO_COLOR` environment variable isn't set. Checked once per process.
+#[cfg(feature = "cli")]
+pub fn use_color() -> bool {
+    static USE_COLOR: OnceLock<bool> = OnceLock::new();
+    *USE_COLOR.get_or_init(|| use_color_on(&stdout()))
+}
+
+/// Returns true if terminal colours and styles should be written to `out`: it's a
+/// terminal and the `NO_COLOR` environment variable isn't set.
+#[cfg(feature = "cli")]
+pub fn use_color_on<T: AsRawFd>(out: &T) -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|val| !val.is_empty());
+    !no_color && termion::is_tty(out)
+}
+
+/// Wraps `text` in a terminal colour or style, or returns it unchanged if `use_color`
+/// is false.
+#[cfg(feature = "cli")]
+pub fn styled<S: fmt::Display>(style: S, text: &str) -> String {
+    styled_if(use_color(), style, text)
 }
 
-const MOD_BASE: u32 = 32768;
+/// Wraps `text` in a terminal colour or style if `color` is true, as returned by
+/// `use_color_on` for output that isn't stdout.
+#[cfg(feature = "cli")]
+pub fn styled_if<S: fmt::Display>(color: bool, style: S, text: &str) -> String {
+    if color {
+        format!("{}{}{}", style, text, style::Reset)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Colours a diagnostic message red when terminal support is enabled.
+#[cfg(feature = "cli")]
+fn red(text: &str) -> String {
+    styled(color::Fg(color::Red), text)
+}
+
+/// The channels connecting CPUs to the thread that reads stdin.
+#[cfg(feature = "cli")]
+struct StdinReader {
+    /// Asks the thread to read another line
+    requests: chan::Sender<()>,
+    /// The lines read, ending in a newline, or empty at the end of the input
+    lines: chan::Receiver<String>,
+    /// Set while a requested line hasn't been received yet
+    pending: AtomicBool,
+}
+
+/// Returns the reader shared by every CPU, starting its thread on first use.
+///
+/// The thread only reads when a line is requested, so stdin is left alone for the
+/// debugger prompt while the programme isn't waiting on input.
+#[cfg(feature = "cli")]
+fn stdin_reader() -> &'static StdinReader {
+    static READER: OnceLock<StdinReader> = OnceLock::new();
+
+    READER.get_or_init(|| {
+        let (requests, request_rx) = chan::async();
+        let (line_tx, lines) = chan::sync(0);
+
+        thread::spawn(move || {
+            let stdin = stdin();
+            let mut after_cr = false;
+            for () in request_rx {
+                let mut line = String::new();
+                let mut lock = stdin.lock();
+                let mut byte = [0; 1];
+                // A terminal left in raw mode by the debugger prompt sends carriage
+                // returns, so treat them as newlines and skip the \n of a \r\n pair.
+                while let Ok(1) = lock.read(&mut byte) {
+                    let c = byte[0] as char;
+                    if c == '\n' && after_cr && line.is_empty() {
+                        after_cr = false;
+                        continue;
+                    }
+                    after_cr = c == '\r';
+                    line.push(if after_cr { '\n' } else { c });
+                    if line.ends_with('\n') {
+                        break;
+                    }
+                }
+                // Release stdin before waiting for the line to be taken, so the
+                // debugger prompt can read in the meantime.
+                drop(lock);
+                line_tx.send(line);
+            }
+        });
+
+        StdinReader {
+            requests,
+            lines,
+            pending: AtomicBool::new(false),
+        }
+    })
+}
 
+/// Converts a word to the character that the `out` instruction would print for it.
+pub fn decode_char(val: u16) -> char {
+    char::from_u32(val as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
 
 impl SynCpu {
     /// Constructs a new VM with a given receiver for input.
     /// Returns the VM and a receiver for output.
     pub fn new(data: Data) -> SynCpu {
+        let initial = Arc::new(data.clone());
         SynCpu {
             pc: 0,
             halted: false,
             status: status::Status::default(),
             data: data,
-            stdin_buf: Vec::new(),
+            stdin_buf: VecDeque::new(),
             loud: true,
+            call_stack: Vec::new(),
+            breakpoints: HashMap::new(),
+            watchpoints: HashSet::new(),
+            trap_opcodes: HashSet::new(),
+            watch_hit: None,
+            protected: Vec::new(),
+            devices: Vec::new(),
+            record: None,
+            output: None,
+            out_buf: String::new(),
+            echo: true,
+            read_stdin: true,
+            trace: None,
+            coverage: None,
+            on_self_modify: None,
+            initial,
+            loops: None,
+            stats: stats::Stats::default(),
+            max_steps: None,
+            max_stack_depth: None,
+        }
+    }
+
+    /// Returns a builder for a CPU running `data`, for setting up input, output and
+    /// limits in one expression.
+    pub fn builder(data: Data) -> SynCpuBuilder {
+        SynCpuBuilder::new(data)
+    }
+
+    /// Returns the CPU to the state it was created in without reallocating RAM.
+    ///
+    /// The registers, RAM and stack are restored from a copy taken by `new`, so any
+    /// injections applied beforehand are kept. The call stack and statistics are cleared,
+    /// while breakpoints, watchpoints, trapped opcodes, protected ranges and any buffered
+    /// input are left alone.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.halted = false;
+        self.status = status::Status::default();
+        self.data.registers = self.initial.registers;
+        self.data.stack.clone_from(&self.initial.stack);
+        self.data.ram.copy_from_slice(&self.initial.ram);
+        self.call_stack.clear();
+        self.watch_hit = None;
+        self.stats = stats::Stats::default();
+        if let Some(ref mut loops) = self.loops {
+            loops.steps = 0;
+            loops.seen.clear();
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.iter_mut().for_each(|executed| *executed = false);
+        }
+    }
+
+    /// Queues `input` to be read by `in` after anything already queued.
+    ///
+    /// While queued input remains, `in` reads from it without touching stdin or
+    /// starting the stdin reader thread, so `step` can be driven through `in`
+    /// synchronously, for example from tests.
+    pub fn push_input(&mut self, input: &str) {
+        self.stdin_buf.extend(input.chars());
+    }
+
+    /// Starts recording a `trace::TraceEntry` for every instruction evaluated by `step`.
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Starts marking the RAM addresses of every instruction evaluated by `step` in
+    /// `coverage`. This takes `step_dispatch` off its fast path, so is off by default.
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(vec![false; data::RAM_SIZE]);
+        }
+    }
+
+    /// Calls `callback` with the pc, address, old value and new value of every write
+    /// by `wmem` to an address that has already been evaluated as part of an
+    /// instruction, enabling coverage if it isn't already.
+    pub fn on_self_modify<F: Fn(u16, u16, u16, u16) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.enable_coverage();
+        self.on_self_modify = Some(Arc::new(callback));
+    }
+
+    /// Starts halting with `Status::LoopDetected` if the CPU returns to a state it has
+    /// already been in, comparing `fingerprint` after every `every` steps.
+    ///
+    /// States are forgotten whenever `in` consumes input, as what follows then depends on
+    /// more than the fingerprint. Each sample costs 8 bytes, so a larger interval bounds
+    /// memory on long runs at the cost of noticing a loop later.
+    pub fn enable_loop_detection(&mut self, every: u64) {
+        self.loops = Some(LoopDetector {
+            every: cmp::max(every, 1),
+            steps: 0,
+            seen: HashSet::new(),
+        });
+    }
+
+    /// Records an evaluated instruction in `stats`, then halts if the step limit has been
+    /// reached or loop detection finds a repeated state.
+    fn finish_step(&mut self, opcode: Option<u16>) {
+        self.stats.record(opcode, self.data.stack_depth());
+        if !self.halted && self.max_steps.is_some_and(|max| self.stats.steps >= max) {
+            self.status = Status::StepLimitReached;
+            self.halted = true;
+            self.flush_output();
+        }
+        if self.loops.is_some() {
+            self.check_for_loop();
+        }
+    }
+
+    /// Samples the current state for loop detection, halting if it has been seen before.
+    fn check_for_loop(&mut self) {
+        let sample = match self.loops {
+            Some(ref mut loops) => {
+                loops.steps += 1;
+                loops.steps % loops.every == 0
+            },
+            None => false,
+        };
+        if !sample || self.halted {
+            return;
+        }
+
+        let fingerprint = self.fingerprint();
+        let repeated = match self.loops {
+            Some(ref mut loops) => !loops.seen.insert(fingerprint),
+            None => false,
+        };
+        if repeated {
+            self.status = Status::LoopDetected(self.pc);
+            self.halted = true;
+            self.flush_output();
+        }
+    }
+
+    /// Returns true if the stack is as deep as `max_stack_depth` allows.
+    fn stack_full(&self) -> bool {
+        self.max_stack_depth.is_some_and(|max| self.data.stack_depth() >= max)
+    }
+
+    /// Pushes `val` onto the stack, or halts with `Status::StackOverflow` and returns
+    /// false if the stack is full.
+    fn push(&mut self, val: u16) -> bool {
+        if self.stack_full() {
+            self.status = Status::StackOverflow;
+            self.halted = true;
+            return false;
         }
+        self.data.push(val);
+        true
+    }
+
+    /// Maps `device` after any devices already added, returning a handle to it.
+    pub fn add_device<D: MemoryDevice + Send + 'static>(&mut self, device: D) -> Arc<Mutex<D>> {
+        let device = Arc::new(Mutex::new(device));
+        self.devices.push(device.clone());
+        device
+    }
+
+    /// Reads `addr` from the first device that maps it, if any.
+    fn device_read(&self, addr: u16) -> Option<u16> {
+        self.devices.iter().filter_map(|device| device.lock().unwrap().read(addr)).next()
+    }
+
+    /// Writes `val` to `addr` on the first device that maps it, returning false if none do.
+    fn device_write(&self, addr: u16, val: u16) -> bool {
+        self.devices.iter().any(|device| device.lock().unwrap().write(addr, val))
+    }
+
+    /// Returns true if `addr` lies within one of the protected ranges.
+    pub fn is_protected(&self, addr: u16) -> bool {
+        self.protected.iter().any(|range| range.contains(&addr))
     }
 
     /// Returns the next instruction to be evaluated.
@@ -63,45 +453,173 @@ impl SynCpu {
         self.peek_op_at(self.pc)
     }
 
-    /// Returns the next instruction at the given offset in RAM.
+    /// Returns the next instruction at the given offset in RAM, marked as a
+    /// breakpoint if one is set at that address. Offsets past the end of RAM give
+    /// `Instruction::_Unknown`.
     pub fn peek_op_at(&self, offset: u16) -> Operation {
-        Operation::next(&self.data[offset..])
+        let op = Operation::next(self.data.ram.get(offset as usize..).unwrap_or(&[]));
+        if self.breakpoints.get(&offset).is_some_and(|bp| bp.enabled) {
+            Operation::Breakpoint(op.instr())
+        } else {
+            op
+        }
     }
 
-    
-    /// Run the CPU until a breakpoint is hit, exectuion halts
-    /// naturally, or an interrupt signal is received.
+    /// Decodes up to `n` consecutive instructions starting at `start`, stopping early at
+    /// the end of RAM. Each is paired with its address and marked as `peek_op_at` does.
+    pub fn peek_ops(&self, start: u16, n: usize) -> Vec<(u16, Operation)> {
+        self.data.instructions_from(start)
+            .take(n)
+            .map(|(addr, _)| (addr, self.peek_op_at(addr)))
+            .collect()
+    }
+
+    /// Returns a hash of the programme counter and `Data::fingerprint`, for detecting
+    /// when execution returns to an identical state. Not for cryptographic use.
+    pub fn fingerprint(&self) -> u64 {
+        data::fnv_word(self.data.fingerprint(), self.pc)
+    }
+
+    /// Records a hit on any breakpoint at the pc, returning true if running should stop
+    /// there. Only running counts hits; stepping onto a breakpoint doesn't.
+    pub fn hit_breakpoint(&mut self) -> bool {
+        match self.breakpoints.get_mut(&self.pc) {
+            Some(bp) => bp.hit(),
+            None => false,
+        }
+    }
+
+    /// Returns the opcode of the next instruction if it is one of `trap_opcodes`, in
+    /// which case running should stop before it.
+    pub fn hit_trap(&self) -> Option<u16> {
+        if self.trap_opcodes.is_empty() {
+            return None;
+        }
+        self.peek_op().instr().opcode().filter(|op| self.trap_opcodes.contains(op))
+    }
+
+    /// Returns the call sites of the active subroutines, innermost first.
+    pub fn backtrace(&self) -> Vec<u16> {
+        self.call_stack.iter().rev().cloned().collect()
+    }
+
+    /// Run the CPU until a breakpoint is hit, a trapped opcode is reached, a
+    /// watchpoint is written to, or execution halts, without any signal handling or
+    /// terminal output.
+    ///
+    /// A breakpoint or trap at the pc when this is called is passed over, so that
+    /// running again after stopping at one continues execution.
+    pub fn run_headless(&mut self) {
+        let mut resuming = true;
+        while !self.halted && (resuming || !(self.hit_breakpoint() || self.hit_trap().is_some())) {
+            resuming = false;
+            self.step_dispatch();
+            if self.watch_hit.is_some() {
+                break;
+            }
+        }
+        self.flush_output();
+    }
+
+    /// Returns the output from `out` that hasn't been printed yet, including everything
+    /// written while `echo` was off.
+    pub fn buffered_output(&self) -> &str {
+        &self.out_buf
+    }
+
+    /// Prints any output from `out` that is still buffered.
+    ///
+    /// Output is buffered a line at a time and flushed whenever the CPU reads input,
+    /// halts or stops running, so this only needs calling after stepping manually.
+    /// Nothing is printed while `echo` is off.
+    pub fn flush_output(&mut self) {
+        if self.echo && !self.out_buf.is_empty() {
+            let mut out = stdout();
+            let _ = out.write_all(self.out_buf.as_bytes());
+            let _ = out.flush();
+            self.out_buf.clear();
+        }
+    }
+
+    /// Run the CPU until a breakpoint is hit, a trapped opcode is reached, a watchpoint
+    /// is written to, exectuion halts naturally, or an interrupt signal is received.
+    ///
+    /// As with `run_headless`, a breakpoint or trap at the starting pc is passed over.
+    #[cfg(feature = "cli")]
     pub fn run(&mut self) {
+        self.run_until(|_| false);
+    }
+
+    /// Run the CPU until `stop` returns true for the current state, checked
+    /// before each instruction, or until any of the conditions that stop `run`.
+    #[cfg(feature = "cli")]
+    pub fn run_until<F: Fn(&SynCpu) -> bool>(&mut self, stop: F) {
+        self.run_paced(stop, None);
+    }
+
+    /// Run the CPU as `run` does, but sleep for `delay` after each instruction and
+    /// flush any output straight away, so that a run can be followed as it happens.
+    #[cfg(feature = "cli")]
+    pub fn run_throttled(&mut self, delay: Duration) {
+        self.run_paced(|_| false, Some(delay));
+    }
+
+    /// The loop behind `run_until` and `run_throttled`, sleeping for `delay` after
+    /// each instruction if one is given.
+    #[cfg(feature = "cli")]
+    fn run_paced<F: Fn(&SynCpu) -> bool>(&mut self, stop: F, delay: Option<Duration>) {
         let signal = chan_signal::notify(&[Signal::INT, Signal::KILL]);
+        let mut resuming = true;
         
         loop {
             chan_select! {
                 default => {
                     if self.halted {
+                        self.flush_output();
+                        if self.loud && self.status != Status::Ok {
+                            println!("{}", red(&format!("Halted: {}.", self.status)));
+                        } else if self.loud {
+                            println!("{}", red("Halted."));
+                        }
+                        return;
+                    }
+                    if stop(self) {
+                        self.flush_output();
+                        return;
+                    }
+                    if !resuming && self.hit_breakpoint() {
+                        self.flush_output();
+                        if self.loud {
+                            println!("{}", red("Breakpoint hit."));
+                        }
+                        return;
+                    }
+                    if let Some(op) = self.hit_trap().filter(|_| !resuming) {
+                        self.flush_output();
                         if self.loud {
-                            println!("{red}Halted.{reset}",
-                                     red = color::Fg(color::Red),
-                                     reset = style::Reset);
+                            println!("{}", red(&format!("Trapped on {}.", assembler::mnemonic(op).unwrap())));
                         }
                         return;
                     }
-                    let next_op = self.peek_op();
-                    if next_op.is_breakpoint() {
+                    resuming = false;
+                    self.step_dispatch();
+                    if let Some(delay) = delay {
+                        self.flush_output();
+                        thread::sleep(delay);
+                    }
+                    if let Some((addr, old, new)) = self.watch_hit {
+                        self.flush_output();
                         if self.loud {
-                            println!("{red}Breakpoint hit.{reset}",
-                                     red = color::Fg(color::Red),
-                                     reset = style::Reset);
+                            println!("{}", red(&format!("Watchpoint hit: 0x{:0>4x} changed from 0x{:0>4x} to 0x{:0>4x}.",
+                                                        addr, old, new)));
                         }
                         return;
-                    } else {
-                        self.step();
                     }
                 },
                 signal.recv() => {
+                    self.flush_output();
                     if self.loud {
-                        println!("{red}Received signal. Breaking.{reset}",
-                                 red = color::Fg(color::Red),
-                                 reset = style::Reset);
+                        println!("{}", red("Received signal. Breaking."));
                     }
                     return;
                 }
@@ -109,13 +627,106 @@ impl SynCpu {
         }
     }
     
+    /// Reads a line from stdin into `stdin_buf`, returning false if the read was
+    /// interrupted by a signal, or halting with `Status::InputExhausted` at the end
+    /// of the input.
+    #[cfg(feature = "cli")]
+    fn read_line(&mut self) -> bool {
+        let signal = chan_signal::notify(&[Signal::INT, Signal::KILL]);
+        let reader = stdin_reader();
+        let lines = &reader.lines;
+
+        // A line requested before an earlier break is still on its way, so only
+        // ask for another if nothing is outstanding.
+        if !reader.pending.swap(true, Ordering::SeqCst) {
+            reader.requests.send(());
+        }
+
+        chan_select! {
+            signal.recv() => {
+                if self.loud {
+                    println!("{}", red("Breaking during stdin read. The next line entered will be kept as input for the programme."));
+                }
+                return false;
+            },
+            lines.recv() -> line => {
+                reader.pending.store(false, Ordering::SeqCst);
+                match line {
+                    Some(ref line) if !line.is_empty() => {
+                        self.stdin_buf = line.chars().collect();
+                    },
+                    _ => {
+                        self.status = Status::InputExhausted;
+                        self.halted = true;
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Reads a line from stdin into `stdin_buf`, halting with `Status::InputExhausted`
+    /// and returning false at the end of the input.
+    #[cfg(not(feature = "cli"))]
+    fn read_line(&mut self) -> bool {
+        let mut line = String::new();
+        match stdin().read_line(&mut line) {
+            Ok(n) if n > 0 => {
+                self.stdin_buf = line.chars().collect();
+                true
+            },
+            _ => {
+                self.status = Status::InputExhausted;
+                self.halted = true;
+                false
+            }
+        }
+    }
+
     /// Evaluates the next instruction given the system data
     /// returns any potential output for stdout.
+    ///
+    /// The pc never wraps around: if an instruction moves it past the end of RAM, the
+    /// next step halts with `Status::PcOutOfRange` instead of evaluating anything.
     pub fn step(&mut self) {
+        if self.pc as usize >= data::RAM_SIZE {
+            self.status = Status::PcOutOfRange(self.pc);
+            self.halted = true;
+            self.flush_output();
+            return;
+        }
         let next_instr = self.peek_op().instr();
 
+        if self.trace.is_some() {
+            let entry = trace::TraceEntry::capture(self);
+            if let Some(ref mut trace) = self.trace {
+                trace.push(entry);
+            }
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            let start = self.pc as usize;
+            let end = cmp::min(start + next_instr.word_size() as usize, data::RAM_SIZE);
+            coverage[start..end].iter_mut().for_each(|executed| *executed = true);
+        }
+
+        if self.execute(next_instr) {
+            self.finish_step(next_instr.opcode());
+        }
+    }
+
+    /// Evaluates `instr` as though it were the instruction at the pc, whatever RAM
+    /// holds there, then moves the pc past it or to wherever it jumps.
+    ///
+    /// Unlike `step`, this isn't traced or counted in the statistics, and doesn't
+    /// check the step limit or loop detection. Returns false if `in` stopped without
+    /// reading a character, leaving the pc on it. Panics if `instr` is `_Unknown` but
+    /// the words at the pc decode.
+    pub fn execute(&mut self, instr: Instruction) -> bool {
+        self.watch_hit = None;
+
         use self::Instruction::*;
-        match next_instr {
+        match instr {
             Halt => {
                 self.halted = true;
             },
@@ -125,14 +736,15 @@ impl SynCpu {
             },
             Push(src) => {
                 let val = self.data.val(src);
-                self.data.push(val);
+                self.push(val);
             },
             Pop(dst) => {
-                if self.data.is_stack_empty() {
+                if let Some(top) = self.data.peek() {
+                    self.data.pop();
+                    self.data[dst] = top;
+                } else {
                     self.status = Status::PopOnEmptyStack;
                     self.halted = true;
-                } else {
-                    self.data[dst] = self.data.pop();
                 }
             },
             Eq(dst, a, b) => {
@@ -167,16 +779,24 @@ impl SynCpu {
                 }
             },
             Add(dst, a, b) => {
-                let val = (self.data.val(a) as u32 + self.data.val(b) as u32) % MOD_BASE;
-                self.data[dst] = val as u16;
+                let val = SynInt::Literal(self.data.val(a)) + SynInt::Literal(self.data.val(b));
+                self.data[dst] = u16::from(val);
             },
             Mult(dst, a, b) => {
-                let val = (self.data.val(a) as u32 * self.data.val(b) as u32) % MOD_BASE;
-                self.data[dst] = val as u16;
+                let val = SynInt::Literal(self.data.val(a)) * SynInt::Literal(self.data.val(b));
+                self.data[dst] = u16::from(val);
             },
             Mod(dst, a, b) => {
-                let val = self.data.val(a) % self.data.val(b);
-                self.data[dst] = val;
+                match self.data.val(b) {
+                    0 => {
+                        self.status = Status::DivisionByZero(self.pc);
+                        self.halted = true;
+                    },
+                    divisor => {
+                        let val = self.data.val(a) % divisor;
+                        self.data[dst] = val;
+                    },
+                }
             },
             And(dst, a, b) => {
                 let val = self.data.val(a) & self.data.val(b);
@@ -192,80 +812,116 @@ impl SynCpu {
             },
             ReadMem(dst, src) => {
                 let mem_addr = self.data.val(src);
-                let val = self.data[mem_addr];
+                let val = match self.device_read(mem_addr) {
+                    Some(val) => val,
+                    None => self.data[mem_addr],
+                };
                 self.data[dst] = val;
             },
             WriteMem(dst, src) => {
                 let mem_addr = self.data.val(dst);
+                // Addresses past the end of RAM wrap around, as they do when indexing `data`
+                let addr = (mem_addr as usize % data::RAM_SIZE) as u16;
                 let val = self.data.val(src);
-                self.data[mem_addr] = val;
+                if self.is_protected(addr) {
+                    self.status = Status::WriteProtectViolation(addr);
+                    self.halted = true;
+                } else if !self.device_write(addr, val) {
+                    if self.watchpoints.contains(&addr) {
+                        self.watch_hit = Some((addr, self.data[addr], val));
+                    }
+                    let executed = self.coverage.as_ref().is_some_and(|coverage| coverage[addr as usize]);
+                    if let (true, Some(callback)) = (executed, self.on_self_modify.as_ref()) {
+                        callback(self.pc, addr, self.data[addr], val);
+                    }
+                    self.data[addr] = val;
+                }
             },
             Call(dst) => {
-                self.data.push(self.pc + 2);
-                self.pc = self.data.val(dst);
+                let ret_addr = self.pc + 2;
+                if self.push(ret_addr) {
+                    self.call_stack.push(self.pc);
+                    self.pc = self.data.val(dst);
+                }
             },
             Ret => {
                 if self.data.is_stack_empty() {
+                    self.status = Status::ReturnUnderflow;
                     self.halted = true;
                 } else {
                     self.pc = self.data.pop();
+                    if self.call_stack.pop().is_none() {
+                        self.stats.unmatched_rets += 1;
+                    }
                 }
             },
             Out(val) => {
-                let val = self.data.val(val);
-                print!("{}", char::from_u32(val as u32).unwrap());
+                let c = decode_char(self.data.val(val));
+                match self.output {
+                    Some(ref mut output) => output.push(c),
+                    None => {
+                        self.out_buf.push(c);
+                        if c == '\n' {
+                            self.flush_output();
+                        }
+                    },
+                }
             },
             In(dst) => {
+                self.flush_output();
                 if self.stdin_buf.is_empty() {
-                    let signal = chan_signal::notify(&[Signal::INT, Signal::KILL]);
-                    use std::sync::mpsc::{self, TryRecvError};
-                    let (tx, rx) = chan::sync(0);
-                    let (_ctx, crx) = mpsc::channel::<()>();
-
-                    thread::spawn(move || {
-                        let mut buf = String::new();
-                        while let Err(TryRecvError::Empty) = crx.try_recv() {
-                            let mut byte_buf = [0; 1];
-                            stdin().read_exact(&mut byte_buf).unwrap();
-                            let c = char::from_u32(byte_buf[0] as u32).unwrap();
-                            buf.push(c);
-                            if c == '\n' {
-                                let mut buf = buf.chars().collect::<Vec<_>>();
-                                buf.reverse();
-                                tx.send(buf);
-                                return;
-                            }
-                        }
-                    });
-                    
-                    chan_select! {
-                        signal.recv() => {
-                            if self.loud {
-                                println!("{red}Breaking during stdin read. Please enter two newlines before attempting to use the debug prompt.{clear}",
-                                         red = color::Fg(color::Red),
-                                         clear = style::Reset);
-                            }
-                            return;
-                        },
-                        rx.recv() -> buf => {
-                            self.stdin_buf = buf.unwrap();
-                        }
+                    if !self.read_stdin {
+                        self.status = Status::InputExhausted;
+                        self.halted = true;
+                        return false;
+                    }
+                    if !self.read_line() {
+                        return false;
                     }
                 }
-                let c = self.stdin_buf.pop().unwrap();
+                let c = self.stdin_buf.pop_front().unwrap();
+                if let Some(ref mut log) = self.record {
+                    log.push(c);
+                }
+                if let Some(ref mut loops) = self.loops {
+                    loops.seen.clear();
+                }
                 self.data[dst] = c as u16;
             },
             Noop => {
                 
             },
             _Unknown => {
-                self.status = Status::InstructionParseError;
+                let err = Instruction::try_from_words(&self.data[self.pc..])
+                    .expect_err("Only undecodable words are unknown instructions");
+                self.status = Status::InstructionParseError(self.pc, err);
                 self.halted = true;
             }
 
         }
 
+        if self.halted {
+            self.flush_output();
+        }
+
         // The instruction knows how much to increment the pc by
-        self.pc += next_instr.size();
+        self.pc += instr.size();
+        true
+    }
+}
+
+impl fmt::Display for SynCpu {
+    /// Summarises the pc, registers, stack depth and status without any colour.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "pc = 0x{:0>4x}, halted = {}, status = {}", self.pc, self.halted, self.status)?;
+        for (i, val) in self.data.registers.iter().enumerate() {
+            let sep = match i {
+                3 => "\n",
+                7 => "",
+                _ => ", ",
+            };
+            write!(f, "r{} = 0x{:0>4x}{}", i, val, sep)?;
+        }
+        write!(f, "\nstack depth = {}", self.data.stack_depth())
     }
 }
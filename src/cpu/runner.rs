@@ -0,0 +1,30 @@
+//! Headless execution
+//!
+//! Runs a programme to completion without a terminal, feeding it input from a
+//! string and collecting its output.
+
+use cpu::{SynCpu, Data, Status};
+
+/// Runs `binary` until it halts, with `input` as its only source of stdin, and
+/// returns everything it printed.
+///
+/// If the programme faults, or tries to read past the end of `input`, the
+/// status it halted with is returned instead.
+pub fn run_program(binary: &[u8], input: &str) -> Result<String, Status> {
+    let data = Data::from_bin(binary).map_err(|_| Status::InvalidBinary)?;
+
+    let mut cpu = SynCpu::builder(data)
+        .input(input)
+        .read_stdin(false)
+        .capture_output()
+        .quiet()
+        .build()
+        .expect("Only injections can stop a CPU being built");
+
+    cpu.run_headless();
+
+    match cpu.status {
+        Status::Ok => Ok(cpu.output.unwrap_or_default()),
+        status => Err(status),
+    }
+}
@@ -0,0 +1,64 @@
+//! CPU snapshots
+//!
+//! Captures everything a programme can observe about a running CPU, so that a run
+//! can be saved and later resumed from the same point.
+
+use serde_json;
+
+use cpu::{Data, SynCpu};
+
+/// The state of a running CPU, taken by `SynCpu::snapshot` and put back by `SynCpu::restore`.
+///
+/// Debugging state such as breakpoints and watchpoints isn't part of a snapshot.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The address of the next instruction
+    pub pc: u16,
+    /// The registers, RAM and stack
+    pub data: Data,
+    /// The return addresses of the active calls, innermost last
+    pub call_stack: Vec<u16>,
+    /// Input queued for `in` but not yet read
+    pub input: String,
+}
+
+impl Snapshot {
+    /// Serializes the snapshot as a JSON object.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserializes a snapshot written by `to_json`. The length of RAM isn't checked.
+    pub fn from_json(json: &str) -> serde_json::Result<Snapshot> {
+        serde_json::from_str(json)
+    }
+}
+
+impl SynCpu {
+    /// Takes a snapshot of the CPU's current state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            data: self.data.clone(),
+            call_stack: self.call_stack.clone(),
+            input: self.stdin_buf.iter().collect(),
+        }
+    }
+
+    /// Puts the CPU back into the state captured by `snapshot`, ready to run on from there.
+    ///
+    /// The CPU is no longer halted, and loop detection forgets the states it has seen.
+    /// Breakpoints, watchpoints and statistics are left alone.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.pc = snapshot.pc;
+        self.data = snapshot.data;
+        self.call_stack = snapshot.call_stack;
+        self.stdin_buf = snapshot.input.chars().collect();
+        self.halted = false;
+        self.status = Default::default();
+        self.watch_hit = None;
+        if let Some(ref mut loops) = self.loops {
+            loops.seen.clear();
+        }
+    }
+}
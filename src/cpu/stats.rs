@@ -0,0 +1,62 @@
+//! Execution statistics
+
+use cpu::instruction::{OPCODE_COSTS, UNKNOWN_COST};
+
+/// The opcodes counted under each category reported by `Stats::by_category`.
+const CATEGORIES: [(&str, &[u16]); 7] = [
+    ("data", &[1, 15, 16]),
+    ("stack", &[2, 3]),
+    ("comparison", &[4, 5]),
+    ("arithmetic", &[9, 10, 11]),
+    ("bitwise", &[12, 13, 14]),
+    ("control", &[0, 6, 7, 8, 17, 18, 21]),
+    ("io", &[19, 20]),
+];
+
+/// Counts of what the CPU has evaluated since it was created or last reset.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// The number of instructions evaluated, including unknown ones.
+    pub steps: u64,
+    /// The total nominal cost in cycles of the instructions evaluated, as given by
+    /// `Instruction::cost`.
+    pub cycles: u64,
+    /// The number of times each opcode was evaluated, indexed by opcode.
+    pub opcodes: [u64; 22],
+    /// The number of `ret` instructions evaluated with an empty call stack, returning
+    /// to an address no `call` pushed.
+    pub unmatched_rets: u64,
+    /// The greatest number of values the data stack has held.
+    pub max_stack_depth: usize,
+}
+
+impl Stats {
+    /// Records an evaluated instruction and the stack depth it left behind.
+    pub fn record(&mut self, opcode: Option<u16>, stack_depth: usize) {
+        self.steps += 1;
+        self.cycles += opcode.and_then(|op| OPCODE_COSTS.get(op as usize)).map_or(UNKNOWN_COST, |&cost| cost) as u64;
+        if let Some(count) = opcode.and_then(|op| self.opcodes.get_mut(op as usize)) {
+            *count += 1;
+        }
+        if stack_depth > self.max_stack_depth {
+            self.max_stack_depth = stack_depth;
+        }
+    }
+
+    /// The number of `call` instructions evaluated.
+    pub fn calls(&self) -> u64 {
+        self.opcodes[17]
+    }
+
+    /// The number of `ret` instructions evaluated.
+    pub fn rets(&self) -> u64 {
+        self.opcodes[18]
+    }
+
+    /// Returns the number of instructions evaluated in each category of opcode.
+    pub fn by_category(&self) -> Vec<(&'static str, u64)> {
+        CATEGORIES.iter()
+            .map(|&(name, ops)| (name, ops.iter().map(|&op| self.opcodes[op as usize]).sum()))
+            .collect()
+    }
+}
@@ -4,6 +4,8 @@ use std::fmt;
 use std::error;
 use std::default::Default;
 
+use cpu::instruction::DecodeError;
+
 /// An enum listing the different operation states that the CPU can be in at any one time.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Status {
@@ -11,10 +13,28 @@ pub enum Status {
     Ok,
     /// An attempt to pop on an empty stack was performed
     PopOnEmptyStack,
-    /// An instruction could not be parsed
-    InstructionParseError,
+    /// `ret` was evaluated with an empty stack, returning past the programme's entry
+    ReturnUnderflow,
+    /// The instruction at the given address could not be decoded
+    InstructionParseError(u16, DecodeError),
     /// An unimplemented instruction was requested
     UnimplementedInstruction,
+    /// `in` was evaluated with no buffered input while reading stdin was disabled
+    InputExhausted,
+    /// The programme binary could not be loaded
+    InvalidBinary,
+    /// Loop detection found the CPU in a state it had already been in, at the given address
+    LoopDetected(u16),
+    /// `wmem` tried to write to the given address inside a protected range
+    WriteProtectViolation(u16),
+    /// The CPU evaluated as many instructions as `SynCpu::max_steps` allows
+    StepLimitReached,
+    /// The pc was moved to the given address, past the end of RAM
+    PcOutOfRange(u16),
+    /// `push` or `call` would have grown the stack past `SynCpu::max_stack_depth`
+    StackOverflow,
+    /// `mod` at the given address was evaluated with a divisor of zero
+    DivisionByZero(u16),
 }
 
 impl fmt::Display for Status {
@@ -23,8 +43,17 @@ impl fmt::Display for Status {
         match *self {
             Ok => write!(f, "Ok"),
             PopOnEmptyStack => write!(f, "Pop on empty stack"),
-            InstructionParseError => write!(f, "Instruction parse error"),
+            ReturnUnderflow => write!(f, "Return on empty stack"),
+            InstructionParseError(pc, err) => write!(f, "Invalid instruction at 0x{:0>4x}: {}", pc, err),
             UnimplementedInstruction => write!(f, "Unimplemented instruction error"),
+            InputExhausted => write!(f, "Input exhausted"),
+            InvalidBinary => write!(f, "Invalid binary"),
+            LoopDetected(pc) => write!(f, "Loop detected at 0x{:0>4x}", pc),
+            WriteProtectViolation(addr) => write!(f, "Write to protected address 0x{:0>4x}", addr),
+            StepLimitReached => write!(f, "Step limit reached"),
+            PcOutOfRange(pc) => write!(f, "Programme counter out of range: 0x{:0>4x}", pc),
+            StackOverflow => write!(f, "Stack overflow"),
+            DivisionByZero(pc) => write!(f, "Division by zero at 0x{:0>4x}", pc),
         }
     }
 }
@@ -35,8 +64,17 @@ impl error::Error for Status {
         match *self {
             Ok => "Ok",
             PopOnEmptyStack => "Pop on empty stack",
-            InstructionParseError => "Instruction parse error",
+            ReturnUnderflow => "Return on empty stack",
+            InstructionParseError(..) => "Instruction parse error",
             UnimplementedInstruction => "Unimplemented instruction error",
+            InputExhausted => "Input exhausted",
+            InvalidBinary => "Invalid binary",
+            LoopDetected(_) => "Loop detected",
+            WriteProtectViolation(_) => "Write to protected address",
+            StepLimitReached => "Step limit reached",
+            PcOutOfRange(_) => "Programme counter out of range",
+            StackOverflow => "Stack overflow",
+            DivisionByZero(_) => "Division by zero",
         }
     }
 
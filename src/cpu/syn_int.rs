@@ -1,9 +1,15 @@
 //! The integer type used in SynCpu binaries.
 
+use std::error;
 use std::fmt;
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+
+/// Arithmetic on the architecture is performed modulo this value.
+pub const MOD_BASE: u32 = 32768;
 
 /// An enum wrapping the two possible values that an integer can have
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum SynInt {
     /// A literal 16-bit value
     Literal(u16),
@@ -11,6 +17,85 @@ pub enum SynInt {
     Register(usize),
 }
 
+impl SynInt {
+    /// Returns true if this refers to a register.
+    pub fn is_register(&self) -> bool {
+        match *self {
+            SynInt::Register(_) => true,
+            SynInt::Literal(_) => false,
+        }
+    }
+
+    /// Returns true if this is a literal value.
+    pub fn is_literal(&self) -> bool {
+        !self.is_register()
+    }
+
+    /// Returns the literal value, or `None` for a register.
+    pub fn as_literal(&self) -> Option<u16> {
+        match *self {
+            SynInt::Literal(val) => Some(val),
+            SynInt::Register(_) => None,
+        }
+    }
+
+    /// Returns the index of the register, or `None` for a literal.
+    pub fn register_index(&self) -> Option<usize> {
+        match *self {
+            SynInt::Register(reg) => Some(reg),
+            SynInt::Literal(_) => None,
+        }
+    }
+
+    /// Returns the literal value, panicking if this is a register.
+    fn literal(self) -> u32 {
+        match self {
+            SynInt::Literal(val) => val as u32,
+            SynInt::Register(reg) => panic!("Arithmetic on register r{} requires a CPU to resolve its value", reg),
+        }
+    }
+
+    /// Adds two literals modulo 32768.
+    ///
+    /// # Panics
+    /// Panics if either operand is a register.
+    pub fn wrapping_add(self, other: SynInt) -> SynInt {
+        SynInt::Literal(((self.literal() + other.literal()) % MOD_BASE) as u16)
+    }
+
+    /// Subtracts a literal from another modulo 32768.
+    ///
+    /// # Panics
+    /// Panics if either operand is a register.
+    pub fn wrapping_sub(self, other: SynInt) -> SynInt {
+        SynInt::Literal(((self.literal() + MOD_BASE - other.literal() % MOD_BASE) % MOD_BASE) as u16)
+    }
+
+    /// Multiplies two literals modulo 32768.
+    ///
+    /// # Panics
+    /// Panics if either operand is a register.
+    pub fn wrapping_mul(self, other: SynInt) -> SynInt {
+        SynInt::Literal(((self.literal() * other.literal()) % MOD_BASE) as u16)
+    }
+}
+
+impl Add for SynInt {
+    type Output = SynInt;
+
+    fn add(self, other: SynInt) -> SynInt {
+        self.wrapping_add(other)
+    }
+}
+
+impl Mul for SynInt {
+    type Output = SynInt;
+
+    fn mul(self, other: SynInt) -> SynInt {
+        self.wrapping_mul(other)
+    }
+}
+
 impl From<u16> for SynInt {
     fn from(val: u16) -> SynInt {
         if val < 32768 {
@@ -47,3 +132,61 @@ impl fmt::LowerHex for SynInt {
         }
     }
 }
+
+/// An error returned when a string can't be parsed as a `SynInt`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseSynIntError {
+    /// A register other than `r0`-`r7` was named
+    InvalidRegister(String),
+    /// The literal doesn't fit in 15 bits
+    LiteralOutOfRange(String),
+    /// The string is neither a register nor a number
+    Malformed(String),
+}
+
+impl fmt::Display for ParseSynIntError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ParseSynIntError::*;
+        match *self {
+            InvalidRegister(ref s) => write!(f, "`{}` is not a register, expected r0-r7", s),
+            LiteralOutOfRange(ref s) => write!(f, "`{}` is out of range, literals must be below 32768", s),
+            Malformed(ref s) => write!(f, "`{}` is not a register or a number", s),
+        }
+    }
+}
+
+impl error::Error for ParseSynIntError {
+    fn description(&self) -> &str {
+        use self::ParseSynIntError::*;
+        match *self {
+            InvalidRegister(..) => "Invalid register",
+            LiteralOutOfRange(..) => "Literal out of range",
+            Malformed(..) => "Malformed integer",
+        }
+    }
+}
+
+impl FromStr for SynInt {
+    type Err = ParseSynIntError;
+
+    /// Parses a register (`r0`-`r7`), a `0x`-prefixed hexadecimal literal or a decimal literal.
+    fn from_str(s: &str) -> Result<SynInt, ParseSynIntError> {
+        if let Some(reg) = s.strip_prefix('r') {
+            return match reg.parse::<usize>() {
+                Ok(r) if r < 8 => Ok(SynInt::Register(r)),
+                _ => Err(ParseSynIntError::InvalidRegister(s.to_string())),
+            };
+        }
+
+        let val = if let Some(digits) = s.strip_prefix("0x") {
+            u32::from_str_radix(digits, 16)
+        } else {
+            s.parse::<u32>()
+        };
+        match val {
+            Ok(v) if v < MOD_BASE => Ok(SynInt::Literal(v as u16)),
+            Ok(_) => Err(ParseSynIntError::LiteralOutOfRange(s.to_string())),
+            Err(_) => Err(ParseSynIntError::Malformed(s.to_string())),
+        }
+    }
+}
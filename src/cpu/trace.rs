@@ -0,0 +1,42 @@
+//! Execution traces
+//!
+//! Records the state of the CPU before each instruction so that a run can be
+//! exported as JSON for external tools.
+
+use serde_json;
+
+use cpu::SynCpu;
+
+/// The state of the CPU before a single instruction was evaluated.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    /// The address of the instruction
+    pub pc: u16,
+    /// The instruction's opcode, or `None` if it couldn't be decoded
+    pub opcode: Option<u16>,
+    /// The encoded operands of the instruction
+    pub operands: Vec<u16>,
+    /// The registers before the instruction was evaluated
+    pub registers: [u16; 8],
+    /// The number of values on the stack before the instruction was evaluated
+    pub stack_depth: usize,
+}
+
+impl TraceEntry {
+    /// Captures the state of `cpu` before it evaluates its next instruction.
+    pub fn capture(cpu: &SynCpu) -> TraceEntry {
+        let instr = cpu.peek_op().instr();
+        TraceEntry {
+            pc: cpu.pc,
+            opcode: instr.opcode(),
+            operands: instr.operands().into_iter().map(u16::from).collect(),
+            registers: cpu.data.registers,
+            stack_depth: cpu.data.stack_depth(),
+        }
+    }
+}
+
+/// Serializes a trace to a JSON array.
+pub fn to_json(trace: &[TraceEntry]) -> serde_json::Result<String> {
+    serde_json::to_string(trace)
+}
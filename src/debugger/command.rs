@@ -1,12 +1,20 @@
 //! Debugger commands
-use std::collections::HashSet;
+use std::cell::Cell;
 use std::convert::From;
 use std::char;
 use std::u16;
 
 use debugger::Debugger;
+use expr;
+use session::Session;
 
-use synacor::{SynCpu, Data, Operation};
+use synacor::{Data, Operation, Instruction};
+use synacor::cpu::data::RAM_SIZE;
+use synacor::cpu::decode_char;
+use synacor::cpu::syn_int::SynInt;
+use synacor::cpu::styled;
+
+use termion::color;
 
 /// The commands runnable by the debugger
 #[derive(Debug, PartialEq, Eq)]
@@ -23,8 +31,46 @@ pub enum Command {
     Disassemble,
     DumpMemory,
     SetRegister,
+    SetRegisters,
     PrintStack,
-    Jump
+    Jump,
+    Backtrace,
+    Watchpoint,
+    Next,
+    Finish,
+    Search,
+    PrintString,
+    WriteMemory,
+    FillMemory,
+    Diff,
+    Source,
+    Xref,
+    Feed,
+    Inject,
+    Protect,
+    Unprotect,
+    Context,
+    FindNext,
+    RunTo,
+    RunToInput,
+    Quiet,
+    Loud,
+    Stats,
+    Time,
+    DumpStack,
+    LoadStack,
+    LoadMemory,
+    Eval,
+    Trap,
+    Symbol,
+    SaveSession,
+    LoadSession,
+    WatchAdd,
+    WatchRemove,
+    Asm,
+    SelfModify,
+    Coverage,
+    Strings,
 }
 
 impl<'a> From<&'a str> for Command {
@@ -33,16 +79,54 @@ impl<'a> From<&'a str> for Command {
             "q" | "quit" => Command::Quit,
             "h" | "?" | "help" => Command::Help,
             "s" | "step" => Command::Step,
+            "n" | "next" => Command::Next,
+            "finish" => Command::Finish,
+            "runto" => Command::RunTo,
+            "runin" => Command::RunToInput,
+            "quiet" => Command::Quiet,
+            "stats" => Command::Stats,
+            "time" => Command::Time,
+            "loud" => Command::Loud,
+            "search" => Command::Search,
             "r" | "registers" => Command::Registers,
-            "c" | "run" => Command::Run,
+            "c" | "run" | "continue" => Command::Run,
             "bp" | "breakpoint" => Command::Breakpoint,
             "m" | "memory" => Command::Memory,
+            "str" | "string" => Command::PrintString,
             "restart" => Command::Restart,
             "list" | "l" => Command::Disassemble,
+            "ctx" | "context" => Command::Context,
+            "findnext" => Command::FindNext,
             "dump" => Command::DumpMemory,
+            "dumpstack" => Command::DumpStack,
+            "load" => Command::LoadMemory,
+            "eval" | "=" => Command::Eval,
+            "loadstack" => Command::LoadStack,
+            "diff" => Command::Diff,
+            "source" => Command::Source,
+            "xref" => Command::Xref,
+            "feed" => Command::Feed,
+            "inject" => Command::Inject,
+            "protect" => Command::Protect,
+            "unprotect" => Command::Unprotect,
             "set" => Command::SetRegister,
+            "setregs" => Command::SetRegisters,
+            "write" | "poke" => Command::WriteMemory,
+            "fill" => Command::FillMemory,
             "ps" | "stack" => Command::PrintStack,
             "jump" => Command::Jump,
+            "bt" | "backtrace" => Command::Backtrace,
+            "w" | "watch" => Command::Watchpoint,
+            "trap" => Command::Trap,
+            "sym" | "symbol" => Command::Symbol,
+            "save-session" => Command::SaveSession,
+            "load-session" => Command::LoadSession,
+            "watch-add" => Command::WatchAdd,
+            "watch-remove" => Command::WatchRemove,
+            "asm" => Command::Asm,
+            "selfmod" => Command::SelfModify,
+            "cov" | "coverage" => Command::Coverage,
+            "strings" => Command::Strings,
             _ => Command::Unknown,
         }
     }
@@ -58,16 +142,54 @@ impl Command {
                           after the short forms");
                 println!("\thelp (h, ?)               - Print this message");
                 println!("\tstep (s) [n]              - Step through n instructions (default = 1)");
-                println!("\tregisters (r)             - Print the registers");
-                println!("\trun (c)                   - Run execution until a breakpoint is hit or the CPU halts.");
-                println!("\tbreakpoint (bp)           - Set, unset, or list breakpoints.");
-                println!("\tmemory (m) [lines] [addr] - Print 20 lines of 8 16-bit entries from RAM, starting at addr. Default lines = 10, default addr = pc");
-                println!("\trestart                   - Restart the program.");
+                println!("\tnext (n) [n]              - Step through n instructions, running calls to completion (default = 1)");
+                println!("\tfinish                    - Run until the current subroutine returns.");
+                println!("\trunto [reg] [value]       - Run until register reg (r0-r7) holds value, or the CPU stops.");
+                println!("\trunin                     - Run until the programme is about to read input with none queued.");
+                println!("\tquiet / loud              - Hide the programme's output, or show it again along with anything hidden.");
+                println!("\tregisters (r)             - Print the registers, highlighting any changed since they were last printed.");
+                println!("\trun (c, continue) [n]     - Run execution until the nth breakpoint hit (default = 1) or the CPU halts.");
+                println!("\tbreakpoint (bp)           - Set, unset, enable, disable, or list breakpoints, or break on a stack depth.");
+                println!("\tmemory (m) [dec] [addr] [lines] - Print lines of 8 16-bit entries from RAM in hex, or decimal with dec, starting at addr. Default lines = 10, default addr = pc");
+                println!("\tstring (str) [addr] [z]   - Print the length-prefixed string at addr, or the zero/newline terminated one with z.");
+                println!("\tstrings [min_len]         - List every run of at least min_len printable characters in RAM. (default min_len = 4)");
+                println!("\trestart                   - Restart the program, keeping breakpoints and watchpoints.");
                 println!("\tlist (l) [n] [addr]       - Disassemble the next n instructions, starting at addr. (default n = 10, addr = pc)");
-                println!("\tdump [file]               - Dump the full contents of RAM to the specified file.");
-                println!("\tset [n] [value]           - Set register n to the given (decimal) value.");
+                println!("\tcontext (ctx)             - Disassemble the instructions around the pc, marking the current one.");
+                println!("\tfindnext [op] [addr]      - Find the next instruction with mnemonic op, such as call or ret, after addr. (default addr = pc)");
+                println!("\tdump [--json] [file]      - Dump the full contents of RAM to the specified file, or the registers, stack and RAM as JSON.");
+                println!("\tdump [--rust | --c] [file] - Write RAM to the specified file as a Rust or C array of 16-bit words.");
+                println!("\tload [--json] [file]      - Replace RAM with a dump, also restoring the registers and stack from a JSON one.");
+                println!("\tdumpstack [file]          - Write the stack to the specified file as little-endian words, bottom first.");
+                println!("\tloadstack [file]          - Replace the stack with one written by dumpstack.");
+                println!("\tdiff                      - List the RAM addresses that differ from the loaded binary.");
+                println!("\tset [n] [value]           - Set register n (0-7 or r0-r7) to a decimal, 0x-prefixed hex, or register value.");
+                println!("\tsetregs [value..]         - Set r0, r1, ... in order to up to eight decimal or 0x-prefixed hex values.");
+                println!("\twrite (poke) [addr] [value] - Write value (decimal or 0x-prefixed hex) to RAM at addr (in hexadecimal).");
+                println!("\tfill [start] [end] [value] - Write value to every RAM address from start to end inclusive.");
                 println!("\tstack (ps)                - Print the contents of the stack.");
-                println!("\tjump [addr]               - Set the programme counter to the given address (in hexadecimal).");
+                println!("\tjump [addr]               - Set the programme counter to addr: decimal, 0x-prefixed hexadecimal, or pc+n / pc-n.");
+                println!("\tasm [instr]               - Assemble one instruction and execute it at the pc without writing it to RAM, printing what changed.");
+                println!("\teval (=) [expr]           - Evaluate an expression over registers and literals with + - * % & | ^ and parentheses, modulo 32768.");
+                println!("\tstats                     - Summarise the instructions evaluated, their nominal cost in cycles, and the stack's high-water mark.");
+                println!("\ttime [n]                  - Run n steps, or until the CPU stops, and report the time taken and steps per second.");
+                println!("\tbacktrace (bt)            - Print the call sites of the active subroutines.");
+                println!("\tsource [file]             - Run each line of file as a debugger command. Lines starting with # are ignored.");
+                println!("\txref [addr]               - List the jumps and calls with a literal target of addr (in hexadecimal).");
+                println!("\tinject [file]             - Apply the injections in a JSON file to RAM, or undo the last file with revert.");
+                println!("\tprotect [start] [end]     - Halt on writes to RAM from start to end inclusive, or list the protected ranges.");
+                println!("\tunprotect [addr]          - Remove the protected ranges containing addr, or all of them.");
+                println!("\tfeed [file]               - Queue the contents of file, or a \"quoted line\", as input for the programme.");
+                println!("\tsearch [value] [start] [end] - Find a value, or a \"quoted string\", in RAM between start and end.");
+                println!("\twatch (w)                 - Set, unset, or list watchpoints on writes to RAM.");
+                println!("\twatch-add [item..]        - Print registers (r0-r7) or RAM addresses after every step, or print them now.");
+                println!("\twatch-remove [item..]     - Stop printing the given registers or addresses, or all of them.");
+                println!("\tselfmod [on|off]          - Report writes to RAM that has already been executed as code.");
+                println!("\tcoverage (cov)            - Track executed addresses, report on them, or save and merge them across runs.");
+                println!("\ttrap [op..] / trap clear  - Break before any instruction with the given mnemonics or opcodes, list them, or clear them.");
+                println!("\tsymbol (sym) [addr] [name] - Name an address in listings, or list, unset, clear, save, or load symbols.");
+                println!("\tsave-session [file]       - Save the breakpoints, watchpoints, traps, symbols and CPU state to file as JSON.");
+                println!("\tload-session [file]       - Restore a session saved by save-session.");
             },
             Step => {
                 let times = if args.is_empty() {
@@ -80,32 +202,137 @@ impl Command {
                 };
 
                 for _ in 0..times {
-                    println!(" [0x{:0>4x}]: {}",
-                             dbg.cpu.pc,
-                             dbg.cpu.peek_op());
-                    dbg.cpu.step();
+                    if !step_once(dbg) {
+                        break;
+                    }
                 }
                 
             },
+            Next => {
+                let times = if args.is_empty() {
+                    1
+                } else if let Ok(n) = args[0].parse::<usize>() {
+                    n
+                } else {
+                    println!("Usage: next [n] - n is an optional integer (default: 1), the number of steps to take.");
+                    return;
+                };
+
+                for _ in 0..times {
+                    if let Instruction::Call(_) = dbg.cpu.peek_op().instr() {
+                        // Match the call depth as well as the address so that a
+                        // recursive call to the same routine doesn't stop early.
+                        let ret_addr = dbg.cpu.pc + 2;
+                        let depth = dbg.cpu.call_stack.len();
+                        if !step_once(dbg) {
+                            break;
+                        }
+                        dbg.cpu.run_until(|cpu| cpu.pc == ret_addr && cpu.call_stack.len() == depth);
+                        if dbg.cpu.pc != ret_addr || dbg.cpu.call_stack.len() != depth {
+                            break;
+                        }
+                        print_display(dbg);
+                    } else if !step_once(dbg) {
+                        break;
+                    }
+                }
+            },
+            Finish => {
+                let depth = dbg.cpu.call_stack.len();
+                if depth == 0 {
+                    println!("Not inside a subroutine.");
+                    return;
+                }
+
+                dbg.cpu.run_until(|cpu| cpu.call_stack.len() < depth);
+                if dbg.cpu.call_stack.len() < depth {
+                    println!("Returned to 0x{:0>4x}, r0 = 0x{:0>4x}",
+                             dbg.cpu.pc,
+                             dbg.cpu.data.registers[0]);
+                }
+            },
+            RunTo => {
+                let usage = "Usage: runto [reg] [value] - reg is r0-r7, value is decimal or 0x-prefixed hexadecimal.";
+                let (reg, val) = match (args.first().map(|x| x.parse::<SynInt>()),
+                                        args.get(1).map(|x| x.parse::<SynInt>())) {
+                    (Some(Ok(SynInt::Register(reg))), Some(Ok(SynInt::Literal(val)))) => (reg, val),
+                    _ => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+
+                if dbg.cpu.data.registers[reg] == val {
+                    println!("r{} already holds 0x{:0>4x}.", reg, val);
+                    return;
+                }
+
+                dbg.cpu.run_until(|cpu| cpu.data.registers[reg] == val);
+                if dbg.cpu.data.registers[reg] == val {
+                    println!("r{} = 0x{:0>4x} at 0x{:0>4x}", reg, val, dbg.cpu.pc);
+                }
+            },
+            RunToInput => {
+                let waiting = |cpu: &synacor::SynCpu| {
+                    cpu.stdin_buf.is_empty() && matches!(cpu.peek_op().instr(), Instruction::In(_))
+                };
+                dbg.cpu.run_until(waiting);
+                if waiting(&dbg.cpu) {
+                    dbg.cpu.flush_output();
+                    println!("Waiting for input at 0x{:0>4x}", dbg.cpu.pc);
+                }
+            },
+            Quiet => {
+                dbg.cpu.flush_output();
+                dbg.cpu.echo = false;
+                println!("Programme output will be hidden.");
+            },
+            Loud => {
+                dbg.cpu.echo = true;
+                println!("Programme output will be shown.");
+                if !dbg.cpu.buffered_output().is_empty() {
+                    println!("Output while quiet:");
+                    dbg.cpu.flush_output();
+                }
+            },
             Run => {
-                dbg.cpu.run();
+                let times = match args.first().map(|x| x.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => {
+                        println!("Usage: run [n] - n is an optional positive integer (default: 1), the breakpoint hit to stop at.");
+                        return;
+                    }
+                };
+
+                for i in 1..=times {
+                    if run_to_break(dbg) {
+                        break;
+                    }
+                    let at_breakpoint = !dbg.cpu.halted && dbg.cpu.watch_hit.is_none()
+                        && dbg.cpu.breakpoints.get(&dbg.cpu.pc).is_some_and(|bp| bp.enabled);
+                    if !at_breakpoint {
+                        break;
+                    }
+                    if i < times {
+                        println!("Passed breakpoint hit {} of {} at 0x{:0>4x}", i, times, dbg.cpu.pc);
+                    }
+                }
             },
             Registers => {
-                println!("r0 = 0x{:0>4x}, r1 = 0x{:0>4x}, r2 = 0x{:0>4x}, r3 = 0x{:0>4x}",
-                         dbg.cpu.data.registers[0],
-                         dbg.cpu.data.registers[1],
-                         dbg.cpu.data.registers[2],
-                         dbg.cpu.data.registers[3]);
-                println!("r4 = 0x{:0>4x}, r5 = 0x{:0>4x}, r6 = 0x{:0>4x}, r7 = 0x{:0>4x}",
-                         dbg.cpu.data.registers[4],
-                         dbg.cpu.data.registers[5],
-                         dbg.cpu.data.registers[6],
-                         dbg.cpu.data.registers[7]);
+                let regs = (0..8).map(|i| format_register(dbg, i)).collect::<Vec<_>>();
+                println!("{}", regs[..4].join(", "));
+                println!("{}", regs[4..].join(", "));
+                dbg.prev_registers = dbg.cpu.data.registers;
             },
             Breakpoint => {
-                let usage = "breakpoint list         - Lists all breakpoints.\n\
-                             breakpoint set [addr]   - Set a breakpoint at the given address.\n\
-                             breakpoint unset [addr] - Unset the breakpoint at the given address.";
+                let usage = "breakpoint list           - Lists all breakpoints.\n\
+                             breakpoint set [addr] [ignore n] - Set a breakpoint, passing over its first n hits.\n\
+                             breakpoint unset [addr]   - Unset the breakpoint at the given address.\n\
+                             breakpoint enable [addr]  - Re-enable a disabled breakpoint.\n\
+                             breakpoint disable [addr] - Keep the breakpoint at the given address, but don't stop at it.\n\
+                             breakpoint depth [n]      - Stop running when the stack reaches depth n.\n\
+                             breakpoint depth unset [n] - Remove the breakpoint on stack depth n.";
                 if args.is_empty() {
                     println!("{}", usage);
                     return;
@@ -114,24 +341,34 @@ impl Command {
                 match args[0] {
                     "list" => {
                         println!("Set breakpoints:");
-                        for addr in dbg.breakpoints.iter() {
-                            println!("\t0x{:0>4x}", addr);
+                        let mut breakpoints = dbg.cpu.breakpoints.iter().collect::<Vec<_>>();
+                        breakpoints.sort_by_key(|&(addr, _)| *addr);
+                        for (addr, bp) in breakpoints {
+                            println!("\t0x{:0>4x} ({}, {} hits, {} to ignore)",
+                                     addr,
+                                     if bp.enabled { "enabled" } else { "disabled" },
+                                     bp.hits,
+                                     bp.ignore);
+                        }
+                        for depth in dbg.depth_breakpoints.iter() {
+                            println!("\tstack depth {}", depth);
                         }
                     },
                     "set" => {
-                        let addrs = (&args[1..]).iter()
-                            .map(|addr| if addr.starts_with("0x") {
-                                usize::from_str_radix(&addr[2..], 16)
-                            } else {
-                                usize::from_str_radix(addr, 16)
-                            })
-                            .filter(|addr| addr.is_ok())
-                            .map(|addr| addr.unwrap());
-
-                        for addr in addrs {
-                            if Operation::is_valid(addr, &dbg.cpu.data.ram) {
-                                dbg.cpu.data.ram[addr] |= 0xcc00;
-                                dbg.breakpoints.insert(addr);
+                        let (addrs, ignore) = match args.iter().position(|&arg| arg == "ignore") {
+                            Some(i) => match args.get(i + 1).and_then(|x| x.parse().ok()) {
+                                Some(ignore) => (&args[1..i], ignore),
+                                None => {
+                                    println!("{}", usage);
+                                    return;
+                                }
+                            },
+                            None => (&args[1..], 0),
+                        };
+
+                        for addr in parse_addrs(addrs) {
+                            if Operation::is_valid(addr as usize, &dbg.cpu.data.ram) {
+                                dbg.cpu.breakpoints.insert(addr, synacor::cpu::Breakpoint::new(ignore));
                                 println!("Added breakpoint at 0x{:0>4x}", addr);
                             } else {
                                 println!("Address 0x{:0>4x} is not a valid instruction",
@@ -140,19 +377,8 @@ impl Command {
                         }
                     },
                     "unset" => {
-                        let addrs = (&args[1..]).iter()
-                            .map(|addr| if addr.starts_with("0x") {
-                                usize::from_str_radix(&addr[2..], 16)
-                            } else {
-                                usize::from_str_radix(addr, 16)
-                            })
-                            .filter(|addr| addr.is_ok())
-                            .map(|addr| addr.unwrap());
-
-                        for addr in addrs {
-                            if dbg.breakpoints.contains(&addr) {
-                                dbg.breakpoints.remove(&addr);
-                                dbg.cpu.data.ram[addr] &= 0x00ff;
+                        for addr in parse_addrs(&args[1..]) {
+                            if dbg.cpu.breakpoints.remove(&addr).is_some() {
                                 println!("Breakpoint 0x{:0>4x} removed", addr);
                             } else {
                                 println!("Address 0x{:0>4x} is not a breakpoint.",
@@ -160,6 +386,45 @@ impl Command {
                             }
                         }
                     },
+                    "depth" => {
+                        let (unset, depths) = match args.get(1) {
+                            Some(&"unset") => (true, &args[2..]),
+                            _ => (false, &args[1..]),
+                        };
+                        if depths.is_empty() {
+                            println!("{}", usage);
+                            return;
+                        }
+
+                        for arg in depths {
+                            match arg.parse::<usize>() {
+                                Ok(depth) if unset => {
+                                    if dbg.depth_breakpoints.remove(&depth) {
+                                        println!("Breakpoint on stack depth {} removed", depth);
+                                    } else {
+                                        println!("Stack depth {} has no breakpoint.", depth);
+                                    }
+                                },
+                                Ok(depth) => {
+                                    dbg.depth_breakpoints.insert(depth);
+                                    println!("Added breakpoint on stack depth {}", depth);
+                                },
+                                Err(_) => println!("Invalid stack depth: {}", arg),
+                            }
+                        }
+                    },
+                    "enable" | "disable" => {
+                        let enable = args[0] == "enable";
+                        for addr in parse_addrs(&args[1..]) {
+                            if let Some(bp) = dbg.cpu.breakpoints.get_mut(&addr) {
+                                bp.enabled = enable;
+                                println!("Breakpoint 0x{:0>4x} {}", addr, if enable { "enabled" } else { "disabled" });
+                            } else {
+                                println!("Address 0x{:0>4x} is not a breakpoint.",
+                                         addr);
+                            }
+                        }
+                    },
                     _ => {
                         println!("{}", usage);
                         return;
@@ -167,16 +432,19 @@ impl Command {
                 }
             },
             Memory => {
-                let start = if let Some(n) = args.get(0).and_then(|word| {
-                    if word.starts_with("0x") {
-                        usize::from_str_radix(&word[2..], 16).ok()
+                let decimal = args.first() == Some(&"dec");
+                let args = &args[decimal as usize..];
+                // Decimal words can be up to five digits long
+                let width = if decimal { 5 } else { 4 };
+
+                let start = match args.first() {
+                    Some(arg) => if let Some(addr) = parse_addr(arg) {
+                        addr as usize
                     } else {
-                        usize::from_str_radix(word, 16).ok()
-                    }
-                }) {
-                    n
-                } else {
-                    dbg.cpu.pc as usize
+                        println!("Invalid address: {}", arg);
+                        return;
+                    },
+                    None => dbg.cpu.pc as usize,
                 };
 
                 let lines = if let Some(l) = args.get(1).and_then(|x| x.parse().ok()) {
@@ -185,24 +453,27 @@ impl Command {
                     10
                 };
                 
-                println!("addr  0000 1111 2222 3333 4444 5555 6666 7777  01234567");
-                println!("-----|----|----|----|----|----|----|----|----||--------|");
+                let columns = (0..8).map(|i| i.to_string().repeat(width)).collect::<Vec<_>>();
+                println!("addr  {}  01234567", columns.join(" "));
+                println!("-----|{}|--------|", format!("{}|", "-".repeat(width)).repeat(8));
                 for j in 0..lines {
                     if start + 8*j > dbg.cpu.data.ram.len() {
                         break;
                     }
-                    let mut hexs = String::new();
+                    let mut words = String::new();
                     let mut printable = String::new();
 
                     print!("{:0>4x}: ", start + 8*j);
                     
                     for i in 0..8 {
+                        // start is below RAM_SIZE, so the offset always fits in a u16
                         let offset = start + i + 8*j;
-                        if offset >= dbg.cpu.data.ram.len() {
-                            hexs.push_str("END!");
+                        let val = if let Some(val) = dbg.cpu.data.get(offset as u16) {
+                            val
+                        } else {
+                            words.push_str("END!");
                             break;
-                        }
-                        let val = dbg.cpu.data.ram[offset];
+                        };
                         if let Some(c) = char::from_u32(val as u32) {
                             if c.is_alphanumeric() {
                                 printable.push(c);
@@ -213,16 +484,54 @@ impl Command {
                             printable.push('.');
                         }
 
-                        hexs.push_str(&format!("{:0>4x} ", val));
+                        if decimal {
+                            words.push_str(&format!("{:>5} ", val));
+                        } else {
+                            words.push_str(&format!("{:0>4x} ", val));
+                        }
                     }
-                    println!("{:40} {}", hexs, printable);
+                    println!("{:width$} {}", words, printable, width = 8 * (width + 1));
                 }
             },
+            PrintString => {
+                let usage = "Usage: string [addr] [z] - addr is a hexadecimal address. The string is read as a length \
+                             word followed by that many characters, or until a zero or newline if z is given.";
+                let start = match args.first().and_then(|x| parse_addr(x)) {
+                    Some(addr) => addr as usize,
+                    None => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+
+                let ram = &dbg.cpu.data.ram;
+                let words = match args.get(1) {
+                    None => {
+                        let len = ram[start] as usize;
+                        &ram[start + 1..ram.len().min(start + 1 + len)]
+                    },
+                    Some(&"z") => {
+                        let len = ram[start..].iter()
+                            .position(|&w| w == 0 || w == '\n' as u16)
+                            .unwrap_or(ram.len() - start);
+                        &ram[start..start + len]
+                    },
+                    Some(_) => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+
+                let text = words.iter().map(|&w| decode_char(w)).collect::<String>();
+                println!("0x{:0>4x}: {:?}", start, text);
+            },
             Restart => {
-                let data = Data::from_bin(&dbg.original_binary).unwrap();
-                dbg.cpu = SynCpu::new(data);
+                dbg.cpu.reset();
+                dbg.injected.clear();
                 dbg.cpu.stdin_buf = dbg.original_replay.clone();
-                dbg.breakpoints = HashSet::new();
+                if dbg.cpu.record.is_some() {
+                    dbg.cpu.record = Some(String::new());
+                }
             },
             Disassemble => {
                 let n = if let Some(num) = args.get(0).and_then(|x| x.parse().ok()) {
@@ -231,32 +540,85 @@ impl Command {
                     10
                 };
 
-                let mut pc = if let Some(num) = args.get(1).and_then(|x| x.parse().ok()) {
+                let pc = if let Some(num) = args.get(1).and_then(|x| x.parse().ok()) {
                     num
                 } else {
                     dbg.cpu.pc
                 };
                 
-                for _ in 0..n {
-                    use synacor::Instruction::*;
+                for (addr, op) in dbg.cpu.peek_ops(pc, n) {
+                    print_symbol(dbg, addr);
+                    println!("0x{:0>4x}: {}", addr, name_target(dbg, op.to_string(), &op.instr()));
+                }
+            },
+            Context => {
+                let pc = dbg.cpu.pc;
+                if pc as usize >= RAM_SIZE {
+                    println!("The pc is past the end of RAM.");
+                    return;
+                }
+                let mut addr = rewind(&dbg.cpu.data.ram, pc, CONTEXT_BEFORE);
+                let mut after = 0;
 
-                    let instr = dbg.cpu.peek_op_at(pc);
-                    println!("0x{:0>4x}: {}", pc, instr);
+                while after <= CONTEXT_AFTER && (addr as usize) < RAM_SIZE {
+                    let op = dbg.cpu.peek_op_at(addr);
+                    let marker = if addr == pc { "=>" } else { "  " };
+                    print_symbol(dbg, addr);
+                    println!("{} 0x{:0>4x}: {}", marker, addr, name_target(dbg, op.to_string(), &op.instr()));
 
-                    pc += match instr.instr() {
-                        Halt | Ret => 1,
-                        Jmp(_) | Call(_) => 2,
-                        Jt(_,_) | Jf(_,_) => 3,
-                        x => x.size()
-                    };
+                    if addr >= pc {
+                        after += 1;
+                    }
+                    addr += op.instr().word_size();
+                }
+            },
+            FindNext => {
+                use synacor::cpu::assembler;
+
+                let usage = "Usage: findnext [op] [addr] - op is an instruction mnemonic such as call or ret, \
+                             addr is a hexadecimal address (default: the pc).";
+                let op = match args.first().and_then(|x| assembler::opcode(x)) {
+                    Some((op, _)) => op,
+                    None => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+                let start = match args.get(1).map(|x| parse_addr(x)) {
+                    None => dbg.cpu.pc,
+                    Some(Some(addr)) => addr,
+                    Some(None) => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+
+                // Decoded without breakpoint markers, so the addresses match `list`
+                let found = dbg.cpu.data.instructions_from(start)
+                    .skip(1)
+                    .find(|&(_, instr)| instr.opcode() == Some(op));
+                match found {
+                    Some((addr, instr)) => {
+                        println!("{}: {}", format_addr(dbg, addr), name_target(dbg, instr.to_string(), &instr));
+                    },
+                    None => println!("No {} after 0x{:0>4x}.", assembler::mnemonic(op).unwrap(), start),
                 }
             },
             DumpMemory => {
                 use std::fs::File;
+                use std::io::Write;
                 use byteorder::{LittleEndian, WriteBytesExt};
                     
+                let format = match args.first() {
+                    Some(&format) if format.starts_with("--") => format,
+                    _ => "",
+                };
+                if !["", "--json", "--rust", "--c"].contains(&format) {
+                    println!("Unknown dump format {}, expected --json, --rust or --c.", format);
+                    return;
+                }
                 let mut fname = String::new();
-                for word in args {
+                for word in args.iter().skip(!format.is_empty() as usize) {
                     fname.push_str(word);
                 }
 
@@ -271,59 +633,1197 @@ impl Command {
                 } else {
                     let mut file = file.unwrap();
 
+                    let ram = &dbg.cpu.data.ram;
+                    let dump = match format {
+                        "--json" => dbg.cpu.data.to_json().expect("Failed to serialize memory"),
+                        "--rust" => array_source(ram,
+                                                 &format!("pub static RAM: [u16; {}] = [", ram.len()),
+                                                 "];"),
+                        "--c" => array_source(ram,
+                                              &format!("#include <stdint.h>\n\nstatic const uint16_t RAM[{}] = {{", ram.len()),
+                                              "};"),
+                        _ => String::new(),
+                    };
+                    if !format.is_empty() {
+                        if let Err(e) = file.write_all(dump.as_bytes()) {
+                            println!("Failed to write output file: {}", e);
+                        }
+                        return;
+                    }
+
                     for entry in dbg.cpu.data.ram.iter() {
                         file.write_u16::<LittleEndian>(*entry).unwrap();
                     }
                 }
             },
-            SetRegister => {
-                let reg_num = if let Some(val) = args.get(0)
-                    .and_then(|x| x.parse::<usize>().ok()) {
-                        if val < 8 {
-                            val
-                        } else {
-                            println!("Register number must be between 0 and 7");
+            LoadMemory => {
+                use std::fs::File;
+                use std::io::Read;
+
+                let json = args.first() == Some(&"--json");
+                let path = match args.get(json as usize) {
+                    Some(path) => path,
+                    None => {
+                        println!("Please specify a file to load the memory from");
+                        return;
+                    }
+                };
+
+                let mut bytes = Vec::new();
+                if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)) {
+                    println!("Failed to read {}: {}", path, e);
+                    return;
+                }
+
+                if json {
+                    let data = match String::from_utf8(bytes).map_err(|e| e.to_string())
+                        .and_then(|json| Data::from_json(&json).map_err(|e| e.to_string())) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            println!("Failed to parse {}: {}", path, e);
                             return;
                         }
-                    } else {
+                    };
+                    if data.ram.len() != RAM_SIZE {
+                        println!("{} holds {} words of RAM, not {}.", path, data.ram.len(), RAM_SIZE);
+                        return;
+                    }
+                    dbg.cpu.data = data;
+                    println!("Loaded RAM, registers and stack from {}", path);
+                } else {
+                    if bytes.len() != RAM_SIZE * 2 {
+                        println!("{} is {} bytes long, but a dump of RAM is {}.", path, bytes.len(), RAM_SIZE * 2);
+                        return;
+                    }
+                    dbg.cpu.data.ram = Data::from_bin(&bytes).unwrap().ram;
+                    println!("Loaded RAM from {}", path);
+                }
+            },
+            DumpStack => {
+                use std::fs::File;
+                use std::io::Write;
+                use byteorder::{ByteOrder, LittleEndian};
+
+                let path = match args.first() {
+                    Some(path) => path,
+                    None => {
+                        println!("Please specify a file name for the output");
+                        return;
+                    }
+                };
+
+                let mut bytes = vec![0; dbg.cpu.data.stack_depth() * 2];
+                LittleEndian::write_u16_into(&dbg.cpu.data.stack, &mut bytes);
+                match File::create(path).and_then(|mut f| f.write_all(&bytes)) {
+                    Ok(()) => println!("Wrote {} values to {}", dbg.cpu.data.stack_depth(), path),
+                    Err(e) => println!("Failed to write {}: {}", path, e),
+                }
+            },
+            LoadStack => {
+                use std::fs::File;
+                use std::io::Read;
+                use byteorder::{ByteOrder, LittleEndian};
+
+                let path = match args.first() {
+                    Some(path) => path,
+                    None => {
+                        println!("Please specify a file to load the stack from");
+                        return;
+                    }
+                };
+
+                let mut bytes = Vec::new();
+                if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)) {
+                    println!("Failed to read {}: {}", path, e);
+                    return;
+                }
+                if bytes.len() % 2 != 0 {
+                    println!("{} holds an odd number of bytes, so isn't a list of 16-bit words.", path);
+                    return;
+                }
+
+                let mut stack = vec![0; bytes.len() / 2];
+                LittleEndian::read_u16_into(&bytes, &mut stack);
+                dbg.cpu.data.stack = stack;
+                println!("Loaded {} values from {}", dbg.cpu.data.stack_depth(), path);
+            },
+            Eval => {
+                if args.is_empty() {
+                    println!("Please specify an expression, e.g. eval r0 + 5");
+                    return;
+                }
+                match expr::eval(&args.join(" "), &dbg.cpu.data.registers) {
+                    Ok(val) => println!("{} = 0x{:0>4x}", val, val),
+                    Err(e) => println!("{}", e),
+                }
+            },
+            Diff => {
+                let original = Data::from_bin(&dbg.original_binary).unwrap();
+                let changes = original.diff(&dbg.cpu.data);
+                for &(addr, old, new) in &changes {
+                    println!("\t0x{:0>4x}: 0x{:0>4x} -> 0x{:0>4x}", addr, old, new);
+                }
+                println!("{} words differ from the original binary.", changes.len());
+            },
+            SetRegister => {
+                let reg_num = match args.first().map(|x| x.parse::<SynInt>()) {
+                    Some(Ok(SynInt::Register(r))) => r,
+                    Some(Ok(SynInt::Literal(n))) if n < 8 => n as usize,
+                    _ => {
                         println!("Register number must be between 0 and 7");
                         return;
-                    };
+                    }
+                };
 
-                let val = if let Some(val) = args.get(1)
-                    .and_then(|x| x.parse::<u16>().ok()) {
-                        val
-                    } else {
-                        println!("Register value must be a 16-bit unsigned integer.");
+                let val = match args.get(1).map(|x| x.parse::<SynInt>()) {
+                    Some(Ok(val)) => dbg.cpu.data.val(val),
+                    Some(Err(e)) => {
+                        println!("Invalid register value: {}", e);
                         return;
-                    };
-                
+                    },
+                    None => {
+                        println!("Please specify a value for the register.");
+                        return;
+                    }
+                };
+
                 dbg.cpu.data.registers[reg_num] = val;
             },
+            SetRegisters => {
+                if args.is_empty() || args.len() > dbg.cpu.data.registers.len() {
+                    println!("Usage: setregs [value..] - Up to eight decimal or 0x-prefixed hexadecimal values for r0, r1, ...");
+                    return;
+                }
+
+                let mut vals = Vec::new();
+                for arg in args {
+                    match parse_value(arg) {
+                        Some(val) => vals.push(val),
+                        None => {
+                            println!("Value must be a decimal or 0x-prefixed hexadecimal 16-bit unsigned integer: {}", arg);
+                            return;
+                        }
+                    }
+                }
+
+                dbg.cpu.data.registers[..vals.len()].copy_from_slice(&vals);
+            },
+            WriteMemory => {
+                let addr = if let Some(addr) = args.first().and_then(|x| parse_addr(x)) {
+                    addr
+                } else {
+                    println!("Address must be a hexadecimal value less than 0x{:0>4x}.", RAM_SIZE);
+                    return;
+                };
+
+                let val = if let Some(val) = args.get(1).and_then(|x| parse_value(x)) {
+                    val
+                } else {
+                    println!("Value must be a decimal or 0x-prefixed hexadecimal 16-bit unsigned integer.");
+                    return;
+                };
+
+                let old = dbg.cpu.data[addr];
+                dbg.cpu.data[addr] = val;
+                println!("0x{:0>4x}: 0x{:0>4x} -> 0x{:0>4x}", addr, old, val);
+            },
+            FillMemory => {
+                let usage = "Usage: fill [start] [end] [value] - start and end are hexadecimal addresses, value is \
+                             decimal or 0x-prefixed hexadecimal.";
+                let (start, end, val) = match (args.first().and_then(|x| parse_addr(x)),
+                                               args.get(1).and_then(|x| parse_addr(x)),
+                                               args.get(2).and_then(|x| parse_value(x))) {
+                    (Some(start), Some(end), Some(val)) => (start as usize, end as usize, val),
+                    _ => {
+                        println!("{}", usage);
+                        return;
+                    }
+                };
+
+                if start > end {
+                    println!("The start address must not be after the end address.");
+                    return;
+                }
+
+                for word in &mut dbg.cpu.data.ram[start..end + 1] {
+                    *word = val;
+                }
+                println!("Wrote 0x{:0>4x} to {} words.", val, end - start + 1);
+            },
             PrintStack => {
-                println!("Stack contents: ");
+                let top = match dbg.cpu.data.peek() {
+                    Some(top) => top,
+                    None => {
+                        println!("The stack is empty.");
+                        return;
+                    }
+                };
+
+                println!("Stack contents ({} values, top = 0x{:0>4x}): ", dbg.cpu.data.stack_depth(), top);
                 for (i, val) in dbg.cpu.data.stack.iter().enumerate() {
                     println!("\t[{}]: 0x{:0>4x}", i, val);
                 }
             },
             Jump => {
-                let offset = if let Some(val) = args.get(0)
-                    .and_then(|x| if x.starts_with("0x") {
-                        u16::from_str_radix(&x[2..], 16).ok()
-                    }else {
-                        u16::from_str_radix(x, 16).ok()
-                    }) {
-                        val
-                    } else {
-                        println!("Jump target must be a valid 16-bit hexadecimal unsigned integer.");
+                let target = match args.first().and_then(|x| parse_jump_target(x, dbg.cpu.pc)) {
+                    Some(target) => target,
+                    None => {
+                        println!("Usage: jump [addr] - addr is decimal, 0x-prefixed hexadecimal, or pc+n / pc-n, \
+                                  and must be within RAM.");
                         return;
-                    };
+                    }
+                };
 
-                dbg.cpu.pc = offset;
-            }
-            Quit | Unknown => {}
-        }
-    }
-}
+                dbg.cpu.pc = target;
+            },
+            Stats => {
+                let stats = &dbg.cpu.stats;
+                println!("Steps: {}, cycles: {}", stats.steps, stats.cycles);
+                println!("Stack depth: {} (max {})", dbg.cpu.data.stack_depth(), stats.max_stack_depth);
+                println!("Calls: {}, returns: {} ({} with an empty call stack)", stats.calls(), stats.rets(), stats.unmatched_rets);
+                println!("By category:");
+                for (name, count) in stats.by_category() {
+                    println!("\t{:<12} {}", name, count);
+                }
+            },
+            Time => {
+                use std::time::Instant;
+
+                let limit = match args.first().map(|x| x.parse::<u64>()) {
+                    None => None,
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => {
+                        println!("Usage: time [n] - n is an optional positive number of steps to run.");
+                        return;
+                    }
+                };
+
+                let first_step = dbg.cpu.stats.steps;
+                let start = Instant::now();
+                match limit {
+                    Some(n) => dbg.cpu.run_until(|cpu| cpu.stats.steps - first_step >= n),
+                    None => dbg.cpu.run(),
+                }
+                let secs = start.elapsed().as_secs_f64();
+                let steps = dbg.cpu.stats.steps - first_step;
+
+                if secs > 0.0 {
+                    println!("{} steps in {:.3}s ({:.0} steps per second)", steps, secs, steps as f64 / secs);
+                } else {
+                    println!("{} steps in {:.3}s", steps, secs);
+                }
+            },
+            Backtrace => {
+                let frames = dbg.cpu.backtrace();
+                if frames.is_empty() {
+                    println!("Not inside a subroutine.");
+                    return;
+                }
 
+                println!("Backtrace:");
+                for (i, addr) in frames.iter().enumerate() {
+                    let instr = dbg.cpu.peek_op_at(*addr).instr();
+                    println!("\t#{}: {}: {}", i, format_addr(dbg, *addr), name_target(dbg, instr.to_string(), &instr));
+                }
+            },
+            Watchpoint => {
+                let usage = "watch list         - Lists all watchpoints.\n\
+                             watch set [addr]   - Break on writes to the given address.\n\
+                             watch unset [addr] - Remove the watchpoint at the given address.";
+                if args.is_empty() {
+                    println!("{}", usage);
+                    return;
+                }
 
+                match args[0] {
+                    "list" => {
+                        println!("Set watchpoints:");
+                        for addr in dbg.cpu.watchpoints.iter() {
+                            println!("\t0x{:0>4x} = 0x{:0>4x}", addr, dbg.cpu.data[*addr]);
+                        }
+                    },
+                    "set" => {
+                        for addr in parse_addrs(&args[1..]) {
+                            dbg.cpu.watchpoints.insert(addr);
+                            println!("Added watchpoint at 0x{:0>4x}", addr);
+                        }
+                    },
+                    "unset" => {
+                        for addr in parse_addrs(&args[1..]) {
+                            if dbg.cpu.watchpoints.remove(&addr) {
+                                println!("Watchpoint 0x{:0>4x} removed", addr);
+                            } else {
+                                println!("Address 0x{:0>4x} is not a watchpoint.", addr);
+                            }
+                        }
+                    },
+                    _ => {
+                        println!("{}", usage);
+                    }
+                }
+            },
+            Trap => {
+                use synacor::cpu::assembler;
+
+                if args.is_empty() {
+                    let mut ops = dbg.cpu.trap_opcodes.iter().cloned().collect::<Vec<_>>();
+                    ops.sort();
+                    println!("Trapped opcodes:");
+                    for op in ops {
+                        println!("\t{:>2}: {}", op, assembler::mnemonic(op).unwrap());
+                    }
+                    return;
+                }
+
+                if args[0] == "clear" {
+                    dbg.cpu.trap_opcodes.clear();
+                    println!("Removed all traps.");
+                    return;
+                }
+
+                for arg in args {
+                    let op = match arg.parse::<u16>() {
+                        Ok(op) => Some(op).filter(|&op| assembler::mnemonic(op).is_some()),
+                        Err(_) => assembler::opcode(arg).map(|(op, _)| op),
+                    };
+                    match op {
+                        Some(op) => {
+                            dbg.cpu.trap_opcodes.insert(op);
+                            println!("Trapping {} ({})", assembler::mnemonic(op).unwrap(), op);
+                        },
+                        None => println!("{} is not an instruction mnemonic or opcode.", arg),
+                    }
+                }
+            },
+            Search => {
+                let usage = "Usage: search [value] [start] [end] - value is a decimal or 0x-prefixed hexadecimal word,\n\
+                             or a \"quoted string\". start and end are optional hexadecimal addresses.";
+                let (pattern, consumed) = if let Some((text, consumed)) = quoted(args) {
+                    (text.chars().map(|c| c as u16).collect::<Vec<_>>(), consumed)
+                } else if let Some(val) = args.first().and_then(|x| parse_value(x)) {
+                    (vec![val], 1)
+                } else {
+                    println!("{}", usage);
+                    return;
+                };
+
+                if pattern.is_empty() {
+                    println!("{}", usage);
+                    return;
+                }
+
+                let start = match args.get(consumed).map(|x| parse_addr(x)) {
+                    Some(Some(addr)) => addr as usize,
+                    Some(None) => { println!("{}", usage); return; },
+                    None => 0,
+                };
+                let end = match args.get(consumed + 1).map(|x| parse_addr(x)) {
+                    Some(Some(addr)) => addr as usize,
+                    Some(None) => { println!("{}", usage); return; },
+                    None => RAM_SIZE - 1,
+                };
+                if start > end {
+                    println!("The start address must not be after the end address.");
+                    return;
+                }
+
+                let ram = &dbg.cpu.data.ram[start..end + 1];
+                let hits = ram.windows(pattern.len())
+                    .enumerate()
+                    .filter(|&(_, window)| window == &pattern[..])
+                    .map(|(i, _)| start + i)
+                    .collect::<Vec<_>>();
+
+                for addr in hits.iter().take(MAX_SEARCH_HITS) {
+                    println!("\t0x{:0>4x}", addr);
+                }
+                if hits.len() > MAX_SEARCH_HITS {
+                    println!("... and {} more matches not shown.", hits.len() - MAX_SEARCH_HITS);
+                } else if hits.is_empty() {
+                    println!("No matches found.");
+                }
+            },
+            Source => {
+                let path = if let Some(path) = args.first() {
+                    path
+                } else {
+                    println!("Please specify a script file to run.");
+                    return;
+                };
+
+                match dbg.run_script(path) {
+                    Ok(true) => {},
+                    Ok(false) => println!("Script stopped by quit."),
+                    Err(e) => println!("Failed to read script {}: {}", path, e),
+                }
+            },
+            Xref => {
+                let target = if let Some(addr) = args.first().and_then(|x| parse_addr(x)) {
+                    addr
+                } else {
+                    println!("Usage: xref [addr] - addr is a hexadecimal address.");
+                    return;
+                };
+
+                let mut found = false;
+                for (pc, instr) in dbg.cpu.data.instructions() {
+                    if instr.jump_target() == Some(target) {
+                        println!("\t{}: {}", format_addr(dbg, pc), name_target(dbg, instr.to_string(), &instr));
+                        found = true;
+                    }
+                }
+                if !found {
+                    println!("No references to {} found.", format_addr(dbg, target));
+                }
+            },
+            Feed => {
+                use std::fs::File;
+                use std::io::Read;
+
+                let input = if let Some((text, _)) = quoted(args) {
+                    text + "\n"
+                } else if let Some(path) = args.first() {
+                    let mut buffer = String::new();
+                    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut buffer)) {
+                        println!("Failed to read {}: {}", path, e);
+                        return;
+                    }
+                    buffer
+                } else {
+                    println!("Usage: feed [file] or feed \"text\" - Queue input to be read by the programme.");
+                    return;
+                };
+
+                dbg.cpu.stdin_buf.extend(input.chars());
+                println!("Queued {} characters of input.", input.chars().count());
+            },
+            Inject => {
+                use std::fs::File;
+                use std::io::Read;
+                use synacor::Injection;
+
+                match args.first() {
+                    Some(&"revert") => {
+                        if let Some(batch) = dbg.injected.pop() {
+                            for (injection, saved) in batch.iter().rev() {
+                                injection.revert(&mut dbg.cpu.data, saved);
+                            }
+                            println!("Reverted {} injections.", batch.len());
+                        } else {
+                            println!("No injections to revert.");
+                        }
+                    },
+                    Some(path) => {
+                        let mut buffer = String::new();
+                        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut buffer)) {
+                            println!("Failed to read {}: {}", path, e);
+                            return;
+                        }
+
+                        let mut batch = Vec::new();
+                        for injection in Injection::from_json(&buffer) {
+                            match injection.inject_saved(&mut dbg.cpu.data) {
+                                Ok((range, saved)) => {
+                                    if range.start == range.end {
+                                        println!("\tApplied an injection without a RAM payload.");
+                                    } else {
+                                        println!("\tWrote 0x{:0>4x} - 0x{:0>4x}", range.start, range.end - 1);
+                                    }
+                                    batch.push((injection, saved));
+                                },
+                                Err(e) => println!("Failed to apply injection: {}", e),
+                            }
+                        }
+                        if !batch.is_empty() {
+                            dbg.injected.push(batch);
+                        }
+                    },
+                    None => println!("Usage: inject [file] or inject revert - Apply or undo a JSON injection file."),
+                }
+            },
+            Protect => {
+                if args.is_empty() {
+                    println!("Protected ranges:");
+                    for range in &dbg.cpu.protected {
+                        println!("\t0x{:0>4x} - 0x{:0>4x}", range.start, range.end - 1);
+                    }
+                    return;
+                }
+
+                let (start, end) = match (args.first().and_then(|x| parse_addr(x)),
+                                          args.get(1).and_then(|x| parse_addr(x))) {
+                    (Some(start), Some(end)) => (start, end),
+                    _ => {
+                        println!("Usage: protect [start] [end] - start and end are hexadecimal addresses.");
+                        return;
+                    }
+                };
+
+                if start > end {
+                    println!("The start address must not be after the end address.");
+                    return;
+                }
+
+                dbg.cpu.protected.push(start..end + 1);
+                println!("Protected 0x{:0>4x} - 0x{:0>4x}", start, end);
+            },
+            Unprotect => {
+                match args.first() {
+                    Some(arg) => if let Some(addr) = parse_addr(arg) {
+                        let before = dbg.cpu.protected.len();
+                        dbg.cpu.protected.retain(|range| !range.contains(&addr));
+                        println!("Removed {} protected ranges.", before - dbg.cpu.protected.len());
+                    } else {
+                        println!("Invalid address: {}", arg);
+                    },
+                    None => {
+                        dbg.cpu.protected.clear();
+                        println!("Removed all protected ranges.");
+                    },
+                }
+            },
+            Symbol => {
+                let usage = "symbol list                - Lists all symbols.\n\
+                             symbol [addr] [name]       - Name the given address in listings.\n\
+                             symbol unset [addr]        - Remove the symbol at the given address.\n\
+                             symbol clear               - Remove all symbols.\n\
+                             symbol save / load [file]  - Write the symbols to a file, or add those in one.";
+
+                match args {
+                    ["list"] => {
+                        println!("Symbols:");
+                        let mut symbols = dbg.symbols.iter().collect::<Vec<_>>();
+                        symbols.sort();
+                        for (addr, name) in symbols {
+                            println!("\t0x{:0>4x}: {}", addr, name);
+                        }
+                    },
+                    ["clear"] => {
+                        dbg.symbols.clear();
+                        println!("Removed all symbols.");
+                    },
+                    ["unset", addrs @ ..] => {
+                        for addr in parse_addrs(addrs) {
+                            match dbg.symbols.remove(&addr) {
+                                Some(name) => println!("Symbol {} removed", name),
+                                None => println!("Address 0x{:0>4x} has no symbol.", addr),
+                            }
+                        }
+                    },
+                    ["save", path] => {
+                        use std::fs::File;
+                        use std::io::Write;
+
+                        let mut symbols = dbg.symbols.iter().collect::<Vec<_>>();
+                        symbols.sort();
+                        let contents = symbols.iter()
+                            .map(|&(addr, name)| format!("0x{:0>4x} {}\n", addr, name))
+                            .collect::<String>();
+                        match File::create(path).and_then(|mut f| f.write_all(contents.as_bytes())) {
+                            Ok(()) => println!("Saved {} symbols to {}", symbols.len(), path),
+                            Err(e) => println!("Failed to write {}: {}", path, e),
+                        }
+                    },
+                    ["load", path] => {
+                        use std::fs::File;
+                        use std::io::Read;
+
+                        let mut contents = String::new();
+                        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+                            println!("Failed to read {}: {}", path, e);
+                            return;
+                        }
+
+                        let mut symbols = Vec::new();
+                        for (i, line) in contents.lines().enumerate() {
+                            let words = line.split_whitespace().collect::<Vec<_>>();
+                            match words[..] {
+                                [] => {},
+                                [addr, name] if parse_addr(addr).is_some() && is_symbol_name(name) => {
+                                    symbols.push((parse_addr(addr).unwrap(), name.to_string()));
+                                },
+                                _ => {
+                                    println!("{}:{}: expected an address and a name: {:?}", path, i + 1, line);
+                                    return;
+                                }
+                            }
+                        }
+                        println!("Loaded {} symbols from {}", symbols.len(), path);
+                        dbg.symbols.extend(symbols);
+                    },
+                    [addr, name] => {
+                        let addr = match parse_addr(addr) {
+                            Some(addr) => addr,
+                            None => {
+                                println!("Invalid address: {}", addr);
+                                return;
+                            }
+                        };
+                        if !is_symbol_name(name) {
+                            println!("Symbol names must be letters, digits, and underscores, not starting with a digit.");
+                            return;
+                        }
+                        println!("0x{:0>4x} is now {}", addr, name);
+                        dbg.symbols.insert(addr, name.to_string());
+                    },
+                    _ => println!("{}", usage),
+                }
+            },
+            SaveSession => {
+                let path = if let Some(path) = args.first() {
+                    path
+                } else {
+                    println!("Please specify a file to save the session to.");
+                    return;
+                };
+
+                match Session::new(dbg).save(path) {
+                    Ok(()) => println!("Saved the session to {}", path),
+                    Err(e) => println!("Failed to save the session to {}: {}", path, e),
+                }
+            },
+            LoadSession => {
+                let path = if let Some(path) = args.first() {
+                    path
+                } else {
+                    println!("Please specify a session file to load.");
+                    return;
+                };
+
+                match Session::load(path) {
+                    Ok(session) if session.cpu.data.ram.len() != RAM_SIZE => {
+                        println!("{} doesn't hold {} words of RAM.", path, RAM_SIZE);
+                    },
+                    Ok(session) => {
+                        session.restore(dbg);
+                        println!("Restored the session from {}, pc = 0x{:0>4x}", path, dbg.cpu.pc);
+                    },
+                    Err(e) => println!("Failed to load the session from {}: {}", path, e),
+                }
+            },
+            WatchAdd => {
+                if args.is_empty() {
+                    if dbg.display.is_empty() {
+                        println!("Nothing is being watched.");
+                    } else {
+                        print_display(dbg);
+                    }
+                    return;
+                }
+
+                for arg in args {
+                    match parse_watched(arg) {
+                        Some(item) if dbg.display.contains(&item) => println!("{} is already watched.", arg),
+                        Some(item) => {
+                            dbg.display.push(item);
+                            println!("Watching {}", format_watched(dbg, item));
+                        },
+                        None => println!("{} is not a register (r0-r7) or an address.", arg),
+                    }
+                }
+            },
+            WatchRemove => {
+                if args.is_empty() {
+                    dbg.display.clear();
+                    println!("Removed all watched items.");
+                    return;
+                }
+
+                for arg in args {
+                    match parse_watched(arg).and_then(|item| dbg.display.iter().position(|&x| x == item)) {
+                        Some(i) => {
+                            dbg.display.remove(i);
+                            println!("Stopped watching {}", arg);
+                        },
+                        None => println!("{} is not watched.", arg),
+                    }
+                }
+            },
+            Asm => {
+                use synacor::cpu::assembler;
+
+                let words = match assembler::assemble(&args.join(" ")) {
+                    Ok(words) => words,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+                let instr = match Instruction::try_from_words(&words) {
+                    Ok(instr) if instr.word_size() as usize == words.len() => instr,
+                    _ => {
+                        println!("Usage: asm [instr] - instr is a single instruction, such as add r0 r1 5.");
+                        return;
+                    }
+                };
+
+                let pc = dbg.cpu.pc;
+                if pc as usize >= RAM_SIZE {
+                    println!("The pc is past the end of RAM.");
+                    return;
+                }
+                let ram = dbg.cpu.data.ram.clone();
+                let depth = dbg.cpu.data.stack_depth();
+                dbg.prev_registers = dbg.cpu.data.registers;
+
+                // The instruction was never in RAM, so the pc only moves if it jumps, calls
+                // or returns. Decided up front, as a jump target may be the next address.
+                let falls_through = match instr {
+                    Instruction::Jt(cond, _) => dbg.cpu.data.val(cond) == 0,
+                    Instruction::Jf(cond, _) => dbg.cpu.data.val(cond) != 0,
+                    _ => instr.size() != 0,
+                };
+
+                println!(" [asm]: {}", instr);
+                dbg.cpu.execute(instr);
+                if falls_through {
+                    dbg.cpu.pc = pc;
+                }
+                // Returning should run the instruction really at the pc, not one past it
+                if let Instruction::Call(_) = instr {
+                    if dbg.cpu.data.stack_depth() > depth {
+                        if let Some(ret_addr) = dbg.cpu.data.stack.last_mut() {
+                            *ret_addr = pc;
+                        }
+                    }
+                }
+
+                let mut changes = changed_registers(dbg);
+                for (addr, (old, new)) in ram.iter().zip(dbg.cpu.data.ram.iter()).enumerate() {
+                    if old != new {
+                        changes.push(format!("[0x{:0>4x}] = 0x{:0>4x} (was 0x{:0>4x})", addr, new, old));
+                    }
+                }
+                if dbg.cpu.data.stack_depth() != depth {
+                    changes.push(format!("stack depth = {} (was {})", dbg.cpu.data.stack_depth(), depth));
+                }
+                if dbg.cpu.pc != pc {
+                    changes.push(format!("pc = 0x{:0>4x}", dbg.cpu.pc));
+                }
+
+                if changes.is_empty() {
+                    println!("\t\tNothing changed.");
+                } else {
+                    println!("\t\t{}", changes.join(", "));
+                }
+                if dbg.cpu.halted {
+                    println!("The CPU halted: {}", dbg.cpu.status);
+                }
+            },
+            SelfModify => {
+                match args.first() {
+                    Some(&"on") => {
+                        dbg.cpu.on_self_modify(|pc, addr, old, new| {
+                            println!("Self-modifying write at 0x{:0>4x}: 0x{:0>4x} changed from 0x{:0>4x} to 0x{:0>4x}.",
+                                     pc, addr, old, new);
+                        });
+                        println!("Reporting writes to code executed from now on.");
+                    },
+                    Some(&"off") => {
+                        dbg.cpu.on_self_modify = None;
+                        println!("No longer reporting self-modifying writes.");
+                    },
+                    _ => println!("Usage: selfmod [on|off] - Report writes to RAM that has already been executed as code."),
+                }
+            },
+            Coverage => {
+                let usage = "coverage on / off          - Start or stop marking the addresses of executed instructions.\n\
+                             coverage report            - Print how much of RAM has executed and the largest gaps.\n\
+                             coverage save [file]       - Write the executed addresses to a file, one per line.\n\
+                             coverage load [file]       - Merge the addresses in a file into the coverage.";
+
+                match args {
+                    ["on"] => {
+                        dbg.cpu.enable_coverage();
+                        println!("Tracking coverage from now on.");
+                    },
+                    ["off"] => {
+                        // Self-modifying writes are found through the coverage
+                        if dbg.cpu.on_self_modify.take().is_some() {
+                            println!("No longer reporting self-modifying writes.");
+                        }
+                        dbg.cpu.coverage = None;
+                        println!("No longer tracking coverage.");
+                    },
+                    ["report"] => {
+                        let coverage = match dbg.cpu.coverage {
+                            Some(ref coverage) => coverage,
+                            None => {
+                                println!("Coverage isn't being tracked. Use \"coverage on\" to start.");
+                                return;
+                            }
+                        };
+
+                        let executed = coverage.iter().filter(|&&executed| executed).count();
+                        println!("{} of {} addresses executed ({:.2}%)",
+                                 executed, coverage.len(), 100.0 * executed as f64 / coverage.len() as f64);
+
+                        let mut gaps = Vec::new();
+                        let mut start = None;
+                        for (addr, &executed) in coverage.iter().chain(Some(&true)).enumerate() {
+                            match start {
+                                Some(gap_start) if executed => {
+                                    gaps.push((addr - gap_start, gap_start));
+                                    start = None;
+                                },
+                                None if !executed => start = Some(addr),
+                                _ => {},
+                            }
+                        }
+                        gaps.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+                        if !gaps.is_empty() {
+                            println!("Largest unexecuted gaps:");
+                        }
+                        for &(len, start) in gaps.iter().take(MAX_REPORTED_GAPS) {
+                            println!("\t0x{:0>4x}-0x{:0>4x} ({} words)", start, start + len - 1, len);
+                        }
+                    },
+                    ["save", path] => {
+                        use std::fs::File;
+                        use std::io::Write;
+
+                        let coverage = match dbg.cpu.coverage {
+                            Some(ref coverage) => coverage,
+                            None => {
+                                println!("Coverage isn't being tracked. Use \"coverage on\" to start.");
+                                return;
+                            }
+                        };
+
+                        let addrs = coverage.iter()
+                            .enumerate()
+                            .filter(|&(_, &executed)| executed)
+                            .map(|(addr, _)| addr)
+                            .collect::<Vec<_>>();
+                        let contents = addrs.iter().map(|addr| format!("0x{:0>4x}\n", addr)).collect::<String>();
+                        match File::create(path).and_then(|mut f| f.write_all(contents.as_bytes())) {
+                            Ok(()) => println!("Saved {} executed addresses to {}", addrs.len(), path),
+                            Err(e) => println!("Failed to write {}: {}", path, e),
+                        }
+                    },
+                    ["load", path] => {
+                        use std::fs::File;
+                        use std::io::Read;
+
+                        let mut contents = String::new();
+                        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+                            println!("Failed to read {}: {}", path, e);
+                            return;
+                        }
+
+                        let mut addrs = Vec::new();
+                        for (i, line) in contents.lines().enumerate() {
+                            match line.trim() {
+                                "" => {},
+                                addr => match parse_addr(addr) {
+                                    Some(addr) if (addr as usize) < RAM_SIZE => addrs.push(addr),
+                                    _ => {
+                                        println!("{}:{}: expected an address in RAM: {:?}", path, i + 1, line);
+                                        return;
+                                    }
+                                },
+                            }
+                        }
+
+                        dbg.cpu.enable_coverage();
+                        if let Some(ref mut coverage) = dbg.cpu.coverage {
+                            for &addr in addrs.iter() {
+                                coverage[addr as usize] = true;
+                            }
+                        }
+                        println!("Merged {} executed addresses from {}", addrs.len(), path);
+                    },
+                    _ => println!("{}", usage),
+                }
+            },
+            Strings => {
+                let min_len = match args.first().map(|x| x.parse::<usize>()) {
+                    None => 4,
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => {
+                        println!("Usage: strings [min_len] - min_len is an optional positive integer (default: 4).");
+                        return;
+                    }
+                };
+
+                let strings = dbg.cpu.data.strings(min_len);
+                for (addr, text) in strings.iter() {
+                    println!("\t0x{:0>4x}: {:?}", addr, text);
+                }
+                if strings.is_empty() {
+                    println!("No strings found.");
+                }
+            },
+            Quit | Unknown => {}
+        }
+    }
+}
+
+/// Runs the CPU as `run` does, but also stops when the data stack grows or shrinks to
+/// one of the debugger's depth breakpoints. Returns true if a depth breakpoint stopped it.
+fn run_to_break(dbg: &mut Debugger) -> bool {
+    if dbg.depth_breakpoints.is_empty() {
+        dbg.cpu.run();
+        return false;
+    }
+
+    // Only a change of depth counts, so resuming at a breakpoint's depth doesn't stop
+    let depths = &dbg.depth_breakpoints;
+    let last = Cell::new(dbg.cpu.data.stack_depth());
+    let fired = Cell::new(false);
+    dbg.cpu.run_until(|cpu| {
+        let depth = cpu.data.stack_depth();
+        fired.set(depth != last.get() && depths.contains(&depth));
+        last.set(depth);
+        fired.get()
+    });
+
+    if fired.get() {
+        println!("Stack depth {} reached at 0x{:0>4x}.", dbg.cpu.data.stack_depth(), dbg.cpu.pc);
+    }
+    fired.get()
+}
+
+/// Prints and executes the next instruction. Returns false if execution
+/// should stop because a watchpoint was hit.
+fn step_once(dbg: &mut Debugger) -> bool {
+    println!(" [0x{:0>4x}]: {}",
+             dbg.cpu.pc,
+             dbg.cpu.peek_op());
+    dbg.cpu.step();
+
+    let changed = changed_registers(dbg);
+    if !changed.is_empty() {
+        println!("\t\t{}", changed.join(", "));
+    }
+
+    print_display(dbg);
+
+    if let Some((addr, old, new)) = dbg.cpu.watch_hit {
+        println!("Watchpoint hit: 0x{:0>4x} changed from 0x{:0>4x} to 0x{:0>4x}.",
+                 addr, old, new);
+        return false;
+    }
+    true
+}
+
+/// Formats the registers that have changed since they were last printed, and marks
+/// them as printed.
+fn changed_registers(dbg: &mut Debugger) -> Vec<String> {
+    let changed = (0..8)
+        .filter(|&i| dbg.cpu.data.registers[i] != dbg.prev_registers[i])
+        .map(|i| format_register(dbg, i))
+        .collect::<Vec<_>>();
+    dbg.prev_registers = dbg.cpu.data.registers;
+    changed
+}
+
+/// Prints the current value of each item added with `watch-add`, if there are any.
+fn print_display(dbg: &Debugger) {
+    if !dbg.display.is_empty() {
+        let items = dbg.display.iter().map(|&item| format_watched(dbg, item)).collect::<Vec<_>>();
+        println!("\t\t{}", items.join(", "));
+    }
+}
+
+/// Formats a watched register, or a RAM address given as a literal, with its value.
+fn format_watched(dbg: &Debugger, item: SynInt) -> String {
+    match item {
+        SynInt::Register(reg) => format!("r{} = 0x{:0>4x}", reg, dbg.cpu.data.registers[reg]),
+        SynInt::Literal(addr) => format!("[0x{:0>4x}] = 0x{:0>4x}", addr, dbg.cpu.data[addr]),
+    }
+}
+
+/// Parses an item for `watch-add`: a register `r0`-`r7`, or a hexadecimal address
+/// returned as a literal.
+fn parse_watched(arg: &str) -> Option<SynInt> {
+    if arg.starts_with('r') {
+        arg.parse().ok()
+    } else {
+        parse_addr(arg).map(SynInt::Literal)
+    }
+}
+
+/// Formats register `i` for printing, coloured if it has changed since the registers
+/// were last printed.
+pub fn format_register(dbg: &Debugger, i: usize) -> String {
+    let val = dbg.cpu.data.registers[i];
+    if val == dbg.prev_registers[i] {
+        format!("r{} = 0x{:0>4x}", i, val)
+    } else {
+        styled(color::Fg(color::Yellow), &format!("r{} = 0x{:0>4x}", i, val))
+    }
+}
+
+/// Prints the symbol for `addr` as a label line, if it has one.
+fn print_symbol(dbg: &Debugger, addr: u16) {
+    if let Some(name) = dbg.symbols.get(&addr) {
+        println!("{}:", name);
+    }
+}
+
+/// Formats `addr` for printing, followed by its symbol, or the nearest symbol before
+/// it and the offset from there, if there is one.
+fn format_addr(dbg: &Debugger, addr: u16) -> String {
+    let nearest = dbg.symbols.iter()
+        .filter(|&(&start, _)| start <= addr)
+        .max_by_key(|&(&start, _)| start);
+    match nearest {
+        Some((&start, name)) if start == addr => format!("0x{:0>4x} <{}>", addr, name),
+        Some((&start, name)) => format!("0x{:0>4x} <{}+0x{:x}>", addr, name, addr - start),
+        None => format!("0x{:0>4x}", addr),
+    }
+}
+
+/// Replaces the jump or call target at the end of `line`, the formatted `instr`, with
+/// the target's symbol if it has one.
+fn name_target(dbg: &Debugger, line: String, instr: &Instruction) -> String {
+    let name = instr.jump_target().and_then(|dst| Some((dst, dbg.symbols.get(&dst)?)));
+    match name {
+        Some((dst, name)) => {
+            let target = format!("0x{:0>4x}", dst);
+            match line.strip_suffix(&target) {
+                Some(start) => format!("{}{}", start, name),
+                None => line,
+            }
+        },
+        None => line,
+    }
+}
+
+/// Returns true if `name` can be used as a symbol: letters, digits and underscores,
+/// not starting with a digit.
+fn is_symbol_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+}
+
+/// The maximum number of matches printed by the `search` command.
+const MAX_SEARCH_HITS: usize = 32;
+
+/// The number of words on each line of `dump --rust` and `dump --c` output.
+const WORDS_PER_SOURCE_LINE: usize = 8;
+
+/// The number of unexecuted gaps listed by `coverage report`.
+const MAX_REPORTED_GAPS: usize = 5;
+
+/// The number of instructions shown before and after the pc by the `context` command.
+const CONTEXT_BEFORE: usize = 3;
+const CONTEXT_AFTER: usize = 5;
+
+/// Returns the address of the instruction `count` instructions before `pc`, or as close
+/// to that as can be found.
+///
+/// Instructions vary in length, so there's no way to tell where an earlier one starts.
+/// Instead, each address up to four words per instruction back is tried, furthest first,
+/// and the first that decodes into valid instructions landing exactly on `pc` is used.
+/// Data before the pc can occasionally decode by coincidence, so this is a heuristic.
+pub fn rewind(ram: &[u16], pc: u16, count: usize) -> u16 {
+    // Nothing past the end of RAM decodes, so there's nothing to rewind through
+    if pc as usize >= ram.len() {
+        return pc;
+    }
+    let earliest = (pc as usize).saturating_sub(4 * count);
+    for start in earliest..pc as usize {
+        let mut addrs = Vec::new();
+        let mut addr = start;
+        while addr < pc as usize {
+            let instr = Operation::next(&ram[addr..]).instr();
+            if instr == Instruction::_Unknown {
+                break;
+            }
+            addrs.push(addr);
+            addr += instr.word_size() as usize;
+        }
+
+        if addr == pc as usize && !addrs.is_empty() {
+            let skip = addrs.len().saturating_sub(count);
+            return addrs[skip] as u16;
+        }
+    }
+    pc
+}
+
+/// Parses a hexadecimal RAM address, with or without a `0x` prefix.
+/// Returns `None` if the address is malformed or out of range.
+fn parse_addr(arg: &str) -> Option<u16> {
+    let digits = arg.trim_start_matches("0x");
+    match usize::from_str_radix(digits, 16) {
+        Ok(addr) if addr < RAM_SIZE => Some(addr as u16),
+        _ => None,
+    }
+}
+
+/// Parses a list of hexadecimal RAM addresses, reporting and skipping any
+/// that are malformed or out of range.
+fn parse_addrs(args: &[&str]) -> Vec<u16> {
+    let mut addrs = Vec::new();
+    for arg in args {
+        match parse_addr(arg) {
+            Some(addr) => addrs.push(addr),
+            None => println!("Invalid address: {}", arg),
+        }
+    }
+    addrs
+}
+
+/// Parses a jump target given as a value accepted by `parse_value`, or as an offset from
+/// `pc` in the form `pc+n` or `pc-n`. Returns `None` if the target is outside RAM.
+fn parse_jump_target(arg: &str, pc: u16) -> Option<u16> {
+    let target = if let Some(offset) = arg.strip_prefix("pc+") {
+        pc as usize + parse_value(offset)? as usize
+    } else if let Some(offset) = arg.strip_prefix("pc-") {
+        (pc as usize).checked_sub(parse_value(offset)? as usize)?
+    } else {
+        parse_value(arg)? as usize
+    };
+
+    if target < RAM_SIZE {
+        Some(target as u16)
+    } else {
+        None
+    }
+}
+
+/// Formats `words` as the body of an array literal in Rust or C source, between the
+/// lines `open` and `close`.
+fn array_source(words: &[u16], open: &str, close: &str) -> String {
+    let mut source = format!("{}\n", open);
+    for line in words.chunks(WORDS_PER_SOURCE_LINE) {
+        let line = line.iter().map(|word| format!("0x{:0>4x},", word)).collect::<Vec<_>>();
+        source.push_str(&format!("    {}\n", line.join(" ")));
+    }
+    source.push_str(close);
+    source.push('\n');
+    source
+}
+
+/// Parses a 16-bit value given either in decimal or as `0x`-prefixed hexadecimal.
+fn parse_value(arg: &str) -> Option<u16> {
+    if let Some(digits) = arg.strip_prefix("0x") {
+        u16::from_str_radix(digits, 16).ok()
+    } else {
+        arg.parse().ok()
+    }
+}
+
+/// Reassembles a double-quoted string that was split across several arguments.
+/// Returns the string and the number of arguments it spanned.
+fn quoted(args: &[&str]) -> Option<(String, usize)> {
+    if !args.first().is_some_and(|arg| arg.starts_with('"')) {
+        return None;
+    }
+
+    let mut text = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        text.push_str(arg);
+        if text.len() > 1 && text.ends_with('"') {
+            return Some((text[1..text.len() - 1].to_string(), i + 1));
+        }
+    }
+    None
+}
@@ -3,24 +3,45 @@
 //! A simple debugger wrapper for SynCpus.
 
 use synacor::cpu::{SynCpu, Data, Injection};
-use termion::{style};
+use synacor::cpu::injection::Overwritten;
+use synacor::cpu::styled;
+use synacor::cpu::syn_int::SynInt;
+use termion;
+use termion::{clear, color, style};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
 use libc;
 use libc::{SIGINT, signal};
 
 
 use command::Command;
 
+use std::io;
 use std::io::{stdout, stdin, Write, Read};
 use std::convert::Into;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 
 pub struct Debugger {
     pub original_binary: Vec<u8>,
-    pub original_replay: Vec<char>,
+    pub original_replay: VecDeque<char>,
     pub cpu: SynCpu,
-    pub breakpoints: HashSet<usize>,
+    pub history: Vec<String>,
+    /// Injections applied with the `inject` command, one batch per file, so they can be reverted.
+    pub injected: Vec<Vec<(Injection, Overwritten)>>,
+    /// The registers as they were last printed, so changes can be highlighted.
+    pub prev_registers: [u16; 8],
+    /// Names given to addresses with the `symbol` command, shown in place of the addresses.
+    pub symbols: HashMap<u16, String>,
+    /// The registers, and RAM addresses as literals, printed after every step.
+    pub display: Vec<SynInt>,
+    /// Data stack depths that pause `run` when the stack grows or shrinks to them.
+    pub depth_breakpoints: BTreeSet<usize>,
 }
 
+/// The number of commands kept in the history.
+const MAX_HISTORY: usize = 100;
+
 extern "C" fn ignore_interrupt(_: libc::c_int) {
     print!("\n              > ");
     stdout().flush().unwrap();
@@ -40,26 +61,34 @@ fn check_cargo() -> bool{
 
 impl Debugger {
 
-    pub fn new(binary: Vec<u8>, replay: Vec<char>, injections: &[Injection]) -> Debugger {
+    pub fn new(binary: Vec<u8>, replay: VecDeque<char>, injections: &[Injection]) -> Debugger {
         let mut data = Data::from_bin(&binary).unwrap();
 
         for injection in injections {
-            injection.inject(&mut data);
+            if let Err(e) = injection.inject(&mut data) {
+                println!("Failed to apply injection: {}", e);
+            }
         }
         
         let mut cpu = SynCpu::new(data);
         cpu.stdin_buf = replay.clone();
+        let prev_registers = cpu.data.registers;
         
         Debugger {
             original_binary: binary,
             original_replay: replay,
             cpu: cpu,
-            breakpoints: HashSet::new(),
+            history: Vec::new(),
+            injected: Vec::new(),
+            prev_registers,
+            symbols: HashMap::new(),
+            display: Vec::new(),
+            depth_breakpoints: BTreeSet::new(),
         }
         
     }
 
-    pub fn main_loop(&mut self) {
+    pub fn main_loop(&mut self, script: Option<&str>) {
         if check_cargo() {
             println!("Warning! The VM is running under cargo, interrupts handling has been disabled.");
         } else {
@@ -70,37 +99,165 @@ impl Debugger {
                 );
             }
         }
-        println!("{bold}Synacor VM debugger version 0.1.0{reset}",
-                 bold = style::Bold,
-                 reset = style::Reset);
+        println!("{}", styled(style::Bold, "Synacor VM debugger version 0.1.0"));
+
+        if let Some(path) = script {
+            match self.run_script(path) {
+                Ok(true) => {},
+                Ok(false) => return,
+                Err(e) => println!("Failed to read script {}: {}", path, e),
+            }
+        }
        
         loop {
-            print!("\n(SVM: 0x{:0>4x}) > ", self.cpu.pc);
-            stdout().flush().unwrap();
-            let mut buf = String::new();
-            let result = stdin().read_line(&mut buf);
+            println!();
+            let buf = match self.read_command() {
+                Ok(Some(buf)) => buf,
+                Ok(None) => return,
+                Err(_) => {
+                    println!("");
+                    stdout().flush().unwrap();
+                    continue;
+                }
+            };
+            
+            if !self.execute_line(&buf) {
+                return;
+            }
+        }
+    }
 
-            if let Err(_) = result {
-                println!("");
-                stdout().flush().unwrap();
-                continue;
+    /// Parses and executes a single command line. Returns false if the
+    /// command was a request to quit.
+    pub fn execute_line(&mut self, line: &str) -> bool {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        if words.is_empty() {
+            return true;
+        }
+        let cmd: Command = words[0].into();
+        if cmd == Command::Quit {
+            return false;
+        } else if cmd == Command::Unknown {
+            println!("Unknown command: {:?}", line);
+        } else {
+            let unmatched_rets = self.cpu.stats.unmatched_rets;
+            cmd.execute(self, &words[1..]);
+            if self.cpu.stats.unmatched_rets > unmatched_rets {
+                println!("{}", styled(color::Fg(color::Red), "Returned with an empty call stack."));
             }
-            
-            let words = buf.split_whitespace().collect::<Vec<_>>();
+        }
+        true
+    }
 
+    /// Executes each line of the given file as a debugger command, skipping
+    /// blank lines and comments starting with `#`. Returns false if the
+    /// script asked to quit.
+    pub fn run_script(&mut self, path: &str) -> io::Result<bool> {
+        use std::fs::File;
 
-            if words.is_empty() {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
                 continue;
             }
-            let cmd: Command = words[0].into();
-            if cmd == Command::Quit {
-                return;
-            } else if cmd == Command::Unknown {
-                println!("Unknown command: {:?}", buf);
+            println!("{}> {}", path, line);
+            if !self.execute_line(line) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Prompts for and reads the next command, returning `None` at the end of input.
+    ///
+    /// When attached to a terminal the line is read in raw mode so that the up and
+    /// down arrows can recall previous commands from the history.
+    fn read_command(&mut self) -> io::Result<Option<String>> {
+        self.cpu.flush_output();
+        let prompt = format!("(SVM: 0x{:0>4x}) > ", self.cpu.pc);
+
+        let line = if termion::is_tty(&stdin()) {
+            self.read_line_raw(&prompt)?
+        } else {
+            print!("{}", prompt);
+            stdout().flush()?;
+            let mut buf = String::new();
+            if stdin().read_line(&mut buf)? == 0 {
+                None
             } else {
-                cmd.execute(self, &words[1..]);
+                Some(buf)
+            }
+        };
+
+        if let Some(ref line) = line {
+            let line = line.trim();
+            if !line.is_empty() && self.history.last().map(|s| s.as_str()) != Some(line) {
+                self.history.push(line.to_string());
+                if self.history.len() > MAX_HISTORY {
+                    self.history.remove(0);
+                }
             }
         }
+        Ok(line)
+    }
+
+    /// Reads a line from a terminal with basic editing and history recall.
+    fn read_line_raw(&self, prompt: &str) -> io::Result<Option<String>> {
+        let mut out = stdout().into_raw_mode()?;
+        let mut line = String::new();
+        let mut draft = String::new();
+        let mut index = self.history.len();
+
+        write!(out, "{}", prompt)?;
+        out.flush()?;
+
+        for key in stdin().keys() {
+            match key? {
+                Key::Char('\n') => break,
+                Key::Char(c) => {
+                    line.push(c);
+                    write!(out, "{}", c)?;
+                },
+                Key::Backspace if !line.is_empty() => {
+                    line.pop();
+                    write!(out, "\x08 \x08")?;
+                },
+                Key::Up if index > 0 => {
+                    if index == self.history.len() {
+                        draft = line.clone();
+                    }
+                    index -= 1;
+                    line = self.history[index].clone();
+                    write!(out, "\r{}{}{}", clear::CurrentLine, prompt, line)?;
+                },
+                Key::Down if index < self.history.len() => {
+                    index += 1;
+                    line = if index == self.history.len() {
+                        draft.clone()
+                    } else {
+                        self.history[index].clone()
+                    };
+                    write!(out, "\r{}{}{}", clear::CurrentLine, prompt, line)?;
+                },
+                Key::Ctrl('c') => {
+                    line.clear();
+                    break;
+                },
+                Key::Ctrl('d') if line.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                },
+                _ => {}
+            }
+            out.flush()?;
+        }
+
+        write!(out, "\r\n")?;
+        Ok(Some(line))
     }
 
 }
@@ -0,0 +1,146 @@
+//! Expression evaluator
+//!
+//! Evaluates expressions over registers and literals for the `eval` command, with
+//! the same mod 32768 arithmetic as the VM. From loosest to tightest binding the
+//! operators are `|`, `^`, `&`, `+ -`, then `* %`, and parentheses group as usual.
+
+use synacor::cpu::syn_int::{SynInt, MOD_BASE};
+
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+/// A single token of an expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A register or literal, parsed when it is evaluated
+    Operand(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+/// The binary operators, loosest binding first.
+const PRECEDENCE: [&str; 5] = ["|", "^", "&", "+-", "*%"];
+
+/// Evaluates `expr`, reading registers from `registers`.
+pub fn eval(expr: &str, registers: &[u16; 8]) -> Result<u16, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?.into_iter().peekable(),
+        registers,
+    };
+    let val = parser.binary(0)?;
+    match parser.tokens.next() {
+        None => Ok(val),
+        Some(token) => Err(format!("Unexpected {}", describe(&token))),
+    }
+}
+
+/// Splits an expression into operands, operators and parentheses.
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {},
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if PRECEDENCE.iter().any(|ops| ops.contains(c)) => tokens.push(Token::Op(c)),
+            c if c.is_ascii_alphanumeric() => {
+                let mut operand = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    operand.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Operand(operand));
+                continue;
+            },
+            c => return Err(format!("Unexpected character `{}`", c)),
+        }
+        chars.next();
+    }
+    Ok(tokens)
+}
+
+/// Describes a token for an error message.
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Operand(ref s) => format!("`{}`", s),
+        Token::Op(c) => format!("`{}`", c),
+        Token::Open => "`(`".to_string(),
+        Token::Close => "`)`".to_string(),
+    }
+}
+
+/// A recursive descent parser that evaluates as it goes.
+struct Parser<'a> {
+    tokens: Peekable<IntoIter<Token>>,
+    registers: &'a [u16; 8],
+}
+
+impl<'a> Parser<'a> {
+    /// Evaluates a run of operands joined by operators binding at least as tightly
+    /// as those at `level` of `PRECEDENCE`.
+    fn binary(&mut self, level: usize) -> Result<u16, String> {
+        if level == PRECEDENCE.len() {
+            return self.operand();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&Token::Op(op)) = self.tokens.peek() {
+            if !PRECEDENCE[level].contains(op) {
+                break;
+            }
+            self.tokens.next();
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    /// Evaluates a register, a literal or a parenthesised expression.
+    fn operand(&mut self) -> Result<u16, String> {
+        match self.tokens.next() {
+            Some(Token::Operand(s)) => match s.parse::<SynInt>() {
+                Ok(SynInt::Literal(val)) => Ok(val),
+                Ok(SynInt::Register(reg)) => Ok(self.registers[reg]),
+                Err(e) => Err(e.to_string()),
+            },
+            Some(Token::Open) => {
+                let val = self.binary(0)?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(val),
+                    _ => Err("Missing `)`".to_string()),
+                }
+            },
+            Some(token) => Err(format!("Expected a register or number before {}", describe(&token))),
+            None => Err("Expected a register or number at the end".to_string()),
+        }
+    }
+}
+
+/// Applies a binary operator to two values. Registers can hold any 16-bit value, so
+/// both are reduced modulo 32768 first, as the VM's arithmetic would.
+fn apply(op: char, a: u16, b: u16) -> Result<u16, String> {
+    let (a, b) = ((a as u32 % MOD_BASE) as u16, (b as u32 % MOD_BASE) as u16);
+    let (a, b) = (SynInt::Literal(a), SynInt::Literal(b));
+    let val = match op {
+        '+' => a.wrapping_add(b),
+        '-' => a.wrapping_sub(b),
+        '*' => a.wrapping_mul(b),
+        _ => {
+            let (a, b) = (u16::from(a), u16::from(b));
+            return match op {
+                '%' if b == 0 => Err("Division by zero".to_string()),
+                '%' => Ok(a % b),
+                '&' => Ok(a & b),
+                '|' => Ok(a | b),
+                '^' => Ok(a ^ b),
+                _ => unreachable!(),
+            };
+        },
+    };
+    Ok(u16::from(val))
+}
@@ -8,56 +8,108 @@ extern crate termion;
 #[macro_use] extern crate chan;
 extern crate chan_signal;
 extern crate libc;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
 extern crate synacor;
 
 mod command;
 mod debugger;
+mod expr;
+mod session;
+mod tui;
 use debugger::Debugger;
 
-use std::io::Read;
+use std::io::{Read, Write};
 use std::fs::File;
 use std::env::args;
+use std::collections::VecDeque;
+
+#[path = "../args.rs"]
+mod args;
+
+use args::Args;
+
+const USAGE: &str = "Usage: debugger [options] <binary>
+
+Options:
+    --replay file   Feed the contents of file to the programme before reading stdin
+    --inject file   Apply the JSON injections in file before starting
+    --script file   Run each line of file as a debugger command before the prompt
+    --record file   Save every character read by the programme to file on exit
+    --tui           Run full screen";
+
+/// Reads the whole of the file at `path`, naming the file in any error.
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buffer))
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(buffer)
+}
 
 fn main() {
-    let binary = if let Some(val) = args().nth(1) {
-        let mut buffer = Vec::new();
-        let mut in_file = File::open(val)
-            .expect("Failed to open challenge binary.");
-        in_file.read_to_end(&mut buffer)
-            .expect("Failed to read in binary contents.");
-        buffer
-    } else {
-        println!("Usage: debugger <binary> [replay] [injections]");
-        return;
+    let args = match Args::parse(args().skip(1), &["--replay", "--inject", "--script", "--record"], &["--tui"]) {
+        Ok(args) if args.positional().len() == 1 => args,
+        Ok(_) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            return;
+        }
     };
+    let tui = args.flag("--tui");
+    let script = args.option("--script");
+    let record = args.option("--record");
 
-    let replay = if let Some(val) = args().nth(2) {
-        let mut buffer = String::new();
-        let mut replay_file = File::open(val)
-            .expect("Failed to open replay file");
-        replay_file.read_to_string(&mut buffer)
-            .expect("Failed to read in replay file");
-        let mut buffer: Vec<_> = buffer.chars().collect();
-        buffer.reverse();
-        println!("Replay buffer loaded");
-        buffer
-    } else {
-        Vec::new()
+    let files = (read_file(&args.positional()[0]),
+                 args.option("--replay").map(read_file).transpose(),
+                 args.option("--inject").map(read_file).transpose());
+    let (binary, replay, injections) = match files {
+        (Ok(binary), Ok(replay), Ok(injections)) => (binary, replay, injections),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
     };
 
-    let injections = if let Some(val) = args().nth(3) {
-        let mut buffer = String::new();
-        let mut injection_file = File::open(val)
-            .expect("Failed to open injection file");
-        injection_file.read_to_string(&mut buffer)
-            .expect("Failed to read in injection file");
-        synacor::Injection::from_json(&buffer)
-    } else {
-        vec![]
+    let replay = match replay {
+        Some(replay) => {
+            println!("Replay buffer loaded");
+            String::from_utf8_lossy(&replay).chars().collect()
+        },
+        None => VecDeque::new(),
+    };
+    let injections = match injections {
+        Some(json) => synacor::Injection::from_json(&String::from_utf8_lossy(&json)),
+        None => vec![],
     };
    
     let mut dbg = Debugger::new(binary, replay, &injections);
-    dbg.main_loop();
+    if record.is_some() {
+        dbg.cpu.record = Some(String::new());
+    }
+    if !tui {
+        dbg.main_loop(script);
+    } else if !termion::is_tty(&std::io::stdout()) {
+        println!("The --tui mode needs to be run in a terminal.");
+    } else {
+        if let Some(path) = script {
+            if let Err(e) = dbg.run_script(path) {
+                println!("Failed to read script {}: {}", path, e);
+            }
+        }
+        if let Err(e) = tui::run(&mut dbg) {
+            println!("Terminal error: {}", e);
+        }
+    }
+
+    if let (Some(path), Some(log)) = (record, dbg.cpu.record.as_ref()) {
+        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(log.as_bytes())) {
+            println!("Failed to write recording to {}: {}", path, e);
+        }
+    }
     
     println!("Goodbye!");
 }
@@ -0,0 +1,71 @@
+//! Debugger sessions
+//!
+//! Saves the breakpoints, watchpoints, traps, symbols and CPU state to a JSON file,
+//! so that a long debugging session can be closed and picked up again later.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+
+use serde_json;
+
+use synacor::cpu::{Breakpoint, Snapshot};
+
+use debugger::Debugger;
+
+/// Everything saved by `save-session`. Maps are kept as sorted lists so that
+/// session files are stable and readable.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub cpu: Snapshot,
+    pub breakpoints: Vec<(u16, Breakpoint)>,
+    pub watchpoints: Vec<u16>,
+    pub trap_opcodes: Vec<u16>,
+    pub symbols: Vec<(u16, String)>,
+}
+
+impl Session {
+    /// Captures the current session.
+    pub fn new(dbg: &Debugger) -> Session {
+        let mut breakpoints = dbg.cpu.breakpoints.iter().map(|(&addr, &bp)| (addr, bp)).collect::<Vec<_>>();
+        let mut watchpoints = dbg.cpu.watchpoints.iter().cloned().collect::<Vec<_>>();
+        let mut trap_opcodes = dbg.cpu.trap_opcodes.iter().cloned().collect::<Vec<_>>();
+        let mut symbols = dbg.symbols.iter().map(|(&addr, name)| (addr, name.clone())).collect::<Vec<_>>();
+        breakpoints.sort_by_key(|&(addr, _)| addr);
+        watchpoints.sort();
+        trap_opcodes.sort();
+        symbols.sort();
+
+        Session {
+            cpu: dbg.cpu.snapshot(),
+            breakpoints,
+            watchpoints,
+            trap_opcodes,
+            symbols,
+        }
+    }
+
+    /// Writes the session to `path` as JSON.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let json = serde_json::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        File::create(path)?.write_all(json.as_bytes())
+    }
+
+    /// Reads a session written by `save`.
+    pub fn load(path: &str) -> io::Result<Session> {
+        let mut json = String::new();
+        File::open(path)?.read_to_string(&mut json)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replaces the debugger's breakpoints, watchpoints, traps, symbols and CPU state
+    /// with the session's.
+    pub fn restore(self, dbg: &mut Debugger) {
+        dbg.cpu.restore(self.cpu);
+        dbg.cpu.breakpoints = self.breakpoints.into_iter().collect();
+        dbg.cpu.watchpoints = self.watchpoints.into_iter().collect();
+        dbg.cpu.trap_opcodes = self.trap_opcodes.into_iter().collect();
+        dbg.symbols = self.symbols.into_iter().collect();
+        dbg.prev_registers = dbg.cpu.data.registers;
+    }
+}
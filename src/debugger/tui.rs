@@ -0,0 +1,297 @@
+//! Full screen debugger
+//!
+//! An alternative to the line based prompt that keeps the disassembly around the pc,
+//! the registers and stack, and the programme's output on screen at once.
+
+use synacor::{Instruction, Status};
+use synacor::cpu::assembler;
+use termion;
+use termion::{clear, cursor, style};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use command::{format_register, rewind};
+use debugger::Debugger;
+
+use std::io;
+use std::io::{stdout, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of instructions evaluated between checks for a key press while running.
+const RUN_BATCH: usize = 10_000;
+
+/// How long to wait between checks for a key press while paused.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The shortest time between redraws while running.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The keys shown in the status line when there is nothing else to report.
+const HELP: &str = "s: step  c: continue  i: input  q: quit  (any key pauses)";
+
+/// The state of the full screen debugger between key presses.
+struct Tui<'a> {
+    dbg: &'a mut Debugger,
+    /// Whether the CPU is being run in batches between checks for a key press.
+    running: bool,
+    /// Set when running starts, so that a breakpoint at the starting pc is passed over
+    /// as `SynCpu::run` does.
+    resuming: bool,
+    /// The line being typed for the programme, if input is being entered.
+    input: Option<String>,
+    /// A message shown in the status line in place of the help.
+    message: Option<String>,
+}
+
+/// Runs the debugger full screen until the user quits.
+///
+/// Programme output is captured and shown in its own pane rather than printed, and
+/// input is typed into the status line whenever the programme asks for it.
+pub fn run(dbg: &mut Debugger) -> io::Result<()> {
+    if dbg.cpu.output.is_none() {
+        dbg.cpu.output = Some(String::new());
+    }
+
+    let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+    let mut keys = termion::async_stdin().keys();
+    let mut tui = Tui {
+        dbg,
+        running: false,
+        resuming: false,
+        input: None,
+        message: None,
+    };
+
+    write!(screen, "{}", cursor::Hide)?;
+    let mut dirty = true;
+    let mut last_draw = Instant::now();
+    let result = loop {
+        match keys.next() {
+            Some(Ok(key)) => {
+                if !tui.handle_key(key) {
+                    break Ok(());
+                }
+                dirty = true;
+            },
+            Some(Err(e)) => break Err(e),
+            None if tui.running && tui.input.is_none() => {
+                tui.run_batch();
+                dirty = true;
+            },
+            None => thread::sleep(POLL_INTERVAL),
+        }
+
+        // Redrawing after every batch would spend more time drawing than running
+        if dirty && (!tui.running || last_draw.elapsed() >= REDRAW_INTERVAL) {
+            if let Err(e) = tui.draw(&mut screen) {
+                break Err(e);
+            }
+            dirty = false;
+            last_draw = Instant::now();
+        }
+    };
+    write!(screen, "{}", cursor::Show)?;
+    screen.flush()?;
+    result
+}
+
+impl<'a> Tui<'a> {
+    /// Responds to a key press, returning false if the user asked to quit.
+    fn handle_key(&mut self, key: Key) -> bool {
+        if let Some(mut line) = self.input.take() {
+            match key {
+                Key::Char('\n') => {
+                    line.push('\n');
+                    self.dbg.cpu.stdin_buf.extend(line.chars());
+                    self.message = None;
+                },
+                Key::Char(c) => {
+                    line.push(c);
+                    self.input = Some(line);
+                },
+                Key::Backspace => {
+                    line.pop();
+                    self.input = Some(line);
+                },
+                Key::Esc | Key::Ctrl('c') => {
+                    self.running = false;
+                    self.message = Some("Input cancelled.".to_string());
+                },
+                _ => self.input = Some(line),
+            }
+            return true;
+        }
+
+        if self.running {
+            self.running = false;
+            self.message = Some("Paused.".to_string());
+            return true;
+        }
+
+        self.message = None;
+        match key {
+            Key::Char('q') | Key::Ctrl('c') | Key::Ctrl('d') => return false,
+            Key::Char('s') => {
+                self.dbg.prev_registers = self.dbg.cpu.data.registers;
+                if !self.needs_input() && !self.dbg.cpu.halted {
+                    self.dbg.cpu.step();
+                }
+            },
+            Key::Char('c') => {
+                self.dbg.prev_registers = self.dbg.cpu.data.registers;
+                self.running = !self.dbg.cpu.halted;
+                self.resuming = true;
+            },
+            Key::Char('i') => self.input = Some(String::new()),
+            _ => {}
+        }
+        true
+    }
+
+    /// Returns true if the next instruction is `in` with no input buffered, in which
+    /// case the user is prompted for a line.
+    fn needs_input(&mut self) -> bool {
+        let waiting = match self.dbg.cpu.peek_op().instr() {
+            Instruction::In(_) => self.dbg.cpu.stdin_buf.is_empty(),
+            _ => false,
+        };
+        if waiting {
+            self.input = Some(String::new());
+        }
+        waiting
+    }
+
+    /// Runs up to `RUN_BATCH` instructions, stopping early at breakpoints, watched
+    /// writes, a halt, or when the programme needs input.
+    fn run_batch(&mut self) {
+        for _ in 0..RUN_BATCH {
+            let cpu = &mut self.dbg.cpu;
+            if cpu.halted {
+                self.running = false;
+                return;
+            }
+            if !self.resuming && cpu.hit_breakpoint() {
+                self.running = false;
+                self.message = Some("Breakpoint hit.".to_string());
+                return;
+            }
+            if let Some(op) = cpu.hit_trap().filter(|_| !self.resuming) {
+                self.running = false;
+                self.message = Some(format!("Trapped on {}.", assembler::mnemonic(op).unwrap()));
+                return;
+            }
+            if self.needs_input() {
+                // Carry on running once the line has been entered
+                return;
+            }
+
+            let cpu = &mut self.dbg.cpu;
+            cpu.step_dispatch();
+            self.resuming = false;
+            if let Some((addr, old, new)) = cpu.watch_hit {
+                self.running = false;
+                self.message = Some(format!("Watchpoint hit: 0x{:0>4x} changed from 0x{:0>4x} to 0x{:0>4x}.",
+                                            addr, old, new));
+                return;
+            }
+        }
+    }
+
+    /// Redraws every pane.
+    fn draw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = match termion::terminal_size() {
+            Ok((width, height)) if width > 0 && height > 0 => (width, height),
+            _ => (80, 24),
+        };
+        let output_height = height / 3;
+        let top_height = height.saturating_sub(output_height + 1);
+        let split = width * 3 / 5;
+
+        write!(out, "{}", clear::All)?;
+        self.draw_disassembly(out, split, top_height)?;
+        self.draw_state(out, split + 2, width.saturating_sub(split + 2), top_height)?;
+        self.draw_output(out, top_height + 1, width, output_height)?;
+
+        let status = if let Some(ref line) = self.input {
+            format!("Input> {}", line)
+        } else if let Some(ref message) = self.message {
+            message.clone()
+        } else if self.running {
+            "Running...".to_string()
+        } else if self.dbg.cpu.halted && self.dbg.cpu.status != Status::Ok {
+            format!("Halted: {}.  {}", self.dbg.cpu.status, HELP)
+        } else if self.dbg.cpu.halted {
+            format!("Halted.  {}", HELP)
+        } else {
+            HELP.to_string()
+        };
+        write!(out, "{}{}{}{}",
+               cursor::Goto(1, height),
+               style::Invert,
+               fit(&status, width as usize),
+               style::Reset)?;
+        out.flush()
+    }
+
+    /// Draws the instructions around the pc down the left of the screen.
+    fn draw_disassembly<W: Write>(&self, out: &mut W, width: u16, height: u16) -> io::Result<()> {
+        let cpu = &self.dbg.cpu;
+        write!(out, "{}{}Disassembly{}", cursor::Goto(1, 1), style::Bold, style::Reset)?;
+
+        let mut addr = rewind(&cpu.data.ram, cpu.pc, height as usize / 2) as usize;
+        for row in 2..height + 1 {
+            if addr >= cpu.data.ram.len() {
+                break;
+            }
+            let op = cpu.peek_op_at(addr as u16);
+            let line = fit(&format!("0x{:0>4x}: {}", addr, op), width as usize);
+            if addr == cpu.pc as usize {
+                write!(out, "{}{}{}{}", cursor::Goto(1, row), style::Invert, line, style::Reset)?;
+            } else {
+                write!(out, "{}{}", cursor::Goto(1, row), line)?;
+            }
+            addr += op.instr().word_size() as usize;
+        }
+        Ok(())
+    }
+
+    /// Draws the registers and the top of the stack down the right of the screen.
+    fn draw_state<W: Write>(&self, out: &mut W, left: u16, width: u16, height: u16) -> io::Result<()> {
+        let cpu = &self.dbg.cpu;
+        write!(out, "{}{}Registers{}", cursor::Goto(left, 1), style::Bold, style::Reset)?;
+        for i in 0..8 {
+            write!(out, "{}{}", cursor::Goto(left, 2 + i as u16), format_register(&*self.dbg, i))?;
+        }
+        write!(out, "{}{}",
+               cursor::Goto(left, 10),
+               fit(&format!("pc = 0x{:0>4x}, depth = {}", cpu.pc, cpu.call_stack.len()), width as usize))?;
+
+        write!(out, "{}{}Stack ({}){}", cursor::Goto(left, 12), style::Bold, cpu.data.stack_depth(), style::Reset)?;
+        for (row, val) in (13..height + 1).zip(cpu.data.stack.iter().rev()) {
+            write!(out, "{}0x{:0>4x}", cursor::Goto(left, row), val)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the end of the programme's output along the bottom of the screen.
+    fn draw_output<W: Write>(&self, out: &mut W, top: u16, width: u16, height: u16) -> io::Result<()> {
+        write!(out, "{}{}Output{}", cursor::Goto(1, top), style::Bold, style::Reset)?;
+
+        let output = self.dbg.cpu.output.as_ref().map_or("", |output| output.as_str());
+        let lines = output.lines().collect::<Vec<_>>();
+        let shown = height.saturating_sub(1) as usize;
+        let start = lines.len().saturating_sub(shown);
+        for (row, line) in (top + 1..).zip(&lines[start..]) {
+            write!(out, "{}{}", cursor::Goto(1, row), fit(line, width as usize))?;
+        }
+        Ok(())
+    }
+}
+
+/// Truncates `text` to at most `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
@@ -0,0 +1,77 @@
+//! Control flow graph export
+//!
+//! Partitions a disassembly listing into basic blocks and writes them as a
+//! Graphviz dot graph, with an edge for each fall-through and literal branch target.
+
+use synacor::Instruction;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+
+use Item;
+
+/// Returns true if control can continue to the following instruction.
+pub fn falls_through(instr: &Instruction) -> bool {
+    use synacor::Instruction::*;
+
+    !matches!(*instr, Jmp(_) | Ret | Halt | _Unknown)
+}
+
+/// Returns true if the instruction ends a basic block.
+fn ends_block(instr: &Instruction) -> bool {
+    use synacor::Instruction::*;
+
+    matches!(*instr, Jmp(_) | Jt(_, _) | Jf(_, _) | Call(_) | Ret | Halt)
+}
+
+/// Writes the control flow graph of the code in a listing in dot format, up to
+/// but excluding the address `end`. Unknown instructions and strings are not
+/// treated as code.
+pub fn write_dot<W: Write>(out: &mut W, listing: &[(u16, Item)], end: u16) -> io::Result<()> {
+    let code = listing.iter()
+        .take_while(|&&(addr, _)| addr < end)
+        .filter_map(|&(addr, ref item)| match *item {
+            Item::Code(instr) => Some((addr, instr)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let starts = code.iter().map(|&(addr, _)| addr).collect::<HashSet<_>>();
+    let targets = code.iter()
+        .filter_map(|&(_, instr)| instr.jump_target())
+        .filter(|dst| starts.contains(dst))
+        .collect::<HashSet<_>>();
+
+    // Group the instructions into blocks keyed by their first address
+    let mut blocks: BTreeMap<u16, Vec<(u16, Instruction)>> = BTreeMap::new();
+    let mut current: Option<u16> = None;
+    let mut expected = None;
+    for &(addr, instr) in &code {
+        if current.is_none() || expected != Some(addr) || targets.contains(&addr) {
+            current = Some(addr);
+        }
+        blocks.entry(current.unwrap()).or_default().push((addr, instr));
+
+        expected = Some(addr + instr.word_size());
+        if ends_block(&instr) {
+            current = None;
+        }
+    }
+
+    writeln!(out, "digraph cfg {{")?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+    for (start, instrs) in &blocks {
+        writeln!(out, "    n{:0>4x} [label=\"0x{:0>4x}\"];", start, start)?;
+
+        let &(addr, last) = instrs.last().unwrap();
+        let next = addr + last.word_size();
+        if falls_through(&last) && blocks.contains_key(&next) {
+            writeln!(out, "    n{:0>4x} -> n{:0>4x};", start, next)?;
+        }
+        if let Some(dst) = last.jump_target() {
+            if blocks.contains_key(&dst) {
+                writeln!(out, "    n{:0>4x} -> n{:0>4x} [style=dashed];", start, dst)?;
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
@@ -1,13 +1,245 @@
 extern crate synacor;
+#[cfg(feature = "cli")] extern crate termion;
 
-use synacor::{Data, Operation};
+mod cfg;
+
+use synacor::{Data, Instruction};
+use synacor::cpu::DecodeError;
+use synacor::cpu::data::{is_printable, RAM_SIZE};
 
 use std::io::{Read, Write};
 use std::fs::File;
 use std::env::args;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cmp;
+
+#[cfg(feature = "cli")]
+use synacor::cpu::{styled_if, use_color_on};
+#[cfg(feature = "cli")]
+use termion::color;
+
+/// The shortest run of printable words emitted as an `.ascii` directive.
+const MIN_STRING_LEN: usize = 6;
+
+/// The shortest run of printable words listed by `--strings`.
+const MIN_LISTED_STRING_LEN: usize = 4;
+
+/// An entry in the disassembly listing
+enum Item {
+    /// A decoded instruction
+    Code(Instruction),
+    /// A run of printable characters
+    Ascii(String),
+    /// A word that doesn't start a valid instruction
+    Invalid(DecodeError),
+}
+
+/// Formats an instruction, replacing a jump or call target with its label if it has one.
+fn format_instr(instr: &Instruction, labels: &HashSet<u16>) -> String {
+    use Instruction::*;
+
+    match instr.jump_target() {
+        Some(dst) if labels.contains(&dst) => match *instr {
+            Jmp(_) => format!("jmp  L_{:0>4x}", dst),
+            Call(_) => format!("call L_{:0>4x}", dst),
+            Jt(src, _) => format!("jmnz {} L_{:0>4x}", src, dst),
+            Jf(src, _) => format!("jmpz {} L_{:0>4x}", src, dst),
+            _ => unreachable!(),
+        },
+        _ => format!("{:#}", instr),
+    }
+}
+
+/// Colours a formatted instruction for a terminal if `color` is true: the mnemonic,
+/// any register operands, and the jump or call target.
+#[cfg(feature = "cli")]
+fn colorize(line: &str, instr: &Instruction, color: bool) -> String {
+    let (mnemonic, operands) = match line.find(' ') {
+        Some(i) => line.split_at(i),
+        None => (line, ""),
+    };
+    let mut out = styled_if(color, color::Fg(color::Blue), mnemonic);
+
+    // Character literals given to `out` can contain spaces, so are left whole
+    if let Instruction::Out(_) = *instr {
+        if operands.trim_start().starts_with('\'') {
+            out.push_str(operands);
+            return out;
+        }
+    }
+
+    let words = operands.split(' ').collect::<Vec<_>>();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let is_target = instr.jump_target().is_some() && i == words.len() - 1;
+        if is_target {
+            out.push_str(&styled_if(color, color::Fg(color::Green), word));
+        } else if word.starts_with('r') {
+            out.push_str(&styled_if(color, color::Fg(color::Yellow), word));
+        } else {
+            out.push_str(word);
+        }
+    }
+    out
+}
+
+/// Colour needs terminal support, so is never used without the `cli` feature.
+#[cfg(not(feature = "cli"))]
+fn colorize(line: &str, _: &Instruction, _: bool) -> String {
+    line.to_string()
+}
+
+/// Returns true if colour was asked for and `use_color_on` allows it for `out`.
+#[cfg(feature = "cli")]
+fn use_color(requested: bool, out: &File) -> bool {
+    requested && use_color_on(out)
+}
+
+#[cfg(not(feature = "cli"))]
+fn use_color(_: bool, _: &File) -> bool {
+    false
+}
+
+/// Decodes only the code reachable from `entry`, following fall-throughs and literal
+/// jump and call targets. Returns the listing in address order.
+fn trace_code(data: &Data, entry: u16) -> Vec<(u16, Item)> {
+    let mut listing = BTreeMap::new();
+    let mut worklist = vec![entry];
+    while let Some(pc) = worklist.pop() {
+        if listing.contains_key(&pc) {
+            continue;
+        }
+
+        match Instruction::try_from_words(&data[pc..]) {
+            Ok(instr) => {
+                worklist.extend(instr.jump_target());
+                let next = pc as usize + instr.word_size() as usize;
+                if cfg::falls_through(&instr) && next < RAM_SIZE {
+                    worklist.push(next as u16);
+                }
+                listing.insert(pc, Item::Code(instr));
+            },
+            Err(e) => {
+                listing.insert(pc, Item::Invalid(e));
+            },
+        }
+    }
+    listing.into_iter().collect()
+}
+
+/// Reads a comments file, with a hexadecimal address, a colon and the comment on each
+/// line. Blank lines are skipped, and comments given for the same address are joined.
+fn read_comments(path: &str) -> Result<HashMap<u16, String>, String> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let mut comments: HashMap<u16, String> = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.split_once(':').map(|(addr, text)| (parse_addr(addr.trim()), text.trim())) {
+            Some((Some(addr), text)) if addr < RAM_SIZE => {
+                let comment = comments.entry(addr as u16).or_default();
+                if !comment.is_empty() {
+                    comment.push_str("; ");
+                }
+                comment.push_str(text);
+            },
+            _ => return Err(format!("{}:{}: expected an address, a colon and a comment: {:?}", path, i + 1, line)),
+        }
+    }
+    Ok(comments)
+}
+
+/// Removes `option` and the value following it from `args`, returning the value.
+/// Returns an error if the value is missing.
+fn take_option(args: &mut Vec<String>, option: &str) -> Result<Option<String>, ()> {
+    match args.iter().position(|arg| arg == option) {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Ok(Some(args.remove(i)))
+        },
+        Some(_) => Err(()),
+        None => Ok(None),
+    }
+}
+
+/// Parses an address given in hexadecimal, with or without a `0x` prefix, up to and
+/// including the end of RAM.
+fn parse_addr(arg: &str) -> Option<usize> {
+    usize::from_str_radix(arg.strip_prefix("0x").unwrap_or(arg), 16).ok()
+        .filter(|&addr| addr <= RAM_SIZE)
+}
 
 fn main() {
-    let binary = if let Some(val) = args().nth(1) {
+    let usage = "Usage: disassembler [--no-strings | --strings] [--color] [--cfg file] [--start addr] [--end addr] \
+                 [--trace-from addr] [--comments file] <binary> <output>";
+    let mut args = args().collect::<Vec<_>>();
+    let (cfg_file, start, end, trace_from, comments_file) = match (take_option(&mut args, "--cfg"),
+                                                                   take_option(&mut args, "--start"),
+                                                                   take_option(&mut args, "--end"),
+                                                                   take_option(&mut args, "--trace-from"),
+                                                                   take_option(&mut args, "--comments")) {
+        (Ok(cfg_file), Ok(start), Ok(end), Ok(trace_from), Ok(comments_file)) => {
+            (cfg_file, start, end, trace_from, comments_file)
+        },
+        _ => {
+            println!("{}", usage);
+            return;
+        }
+    };
+    let (start, end) = match (start.as_ref().map(|addr| parse_addr(addr)), end.as_ref().map(|addr| parse_addr(addr))) {
+        (Some(None), _) | (_, Some(None)) => {
+            println!("Addresses must be hexadecimal, up to 0x{:0>4x}.", RAM_SIZE);
+            return;
+        },
+        (start, end) => (start.flatten().unwrap_or(0), end.flatten().unwrap_or(RAM_SIZE)),
+    };
+    let trace_from = match trace_from.map(|addr| parse_addr(&addr)) {
+        Some(Some(entry)) if entry < RAM_SIZE => Some(entry as u16),
+        Some(_) => {
+            println!("The entry address must be hexadecimal, below 0x{:0>4x}.", RAM_SIZE);
+            return;
+        },
+        None => None,
+    };
+    if start > end {
+        println!("The start address 0x{:0>4x} is after the end address 0x{:0>4x}.", start, end);
+        return;
+    }
+    let find_strings = if let Some(i) = args.iter().position(|arg| arg == "--no-strings") {
+        args.remove(i);
+        false
+    } else {
+        true
+    };
+    let list_strings = if let Some(i) = args.iter().position(|arg| arg == "--strings") {
+        args.remove(i);
+        true
+    } else {
+        false
+    };
+    let color = if let Some(i) = args.iter().position(|arg| arg == "--color") {
+        args.remove(i);
+        true
+    } else {
+        false
+    };
+
+    let comments = match comments_file.map(|path| read_comments(&path)) {
+        Some(Ok(comments)) => comments,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return;
+        },
+        None => HashMap::new(),
+    };
+
+    let binary = if let Some(val) = args.get(1) {
         let mut buffer = Vec::new();
         let mut in_file = File::open(val)
             .expect("Failed to open binary.");
@@ -15,28 +247,110 @@ fn main() {
             .expect("Failed to read in binary contents.");
         buffer
     } else {
-        println!("Usage: disassembler <binary> <output>");
+        println!("{}", usage);
         return;
     };
 
-    let mut out_file = if let Some(val) = args().nth(2) {
+    let mut out_file = if let Some(val) = args.get(2) {
         File::create(val)
             .expect("Failed to create output file.")
     } else {
-        println!("Usage: disassembler <binary> <output>");
+        println!("{}", usage);
         return;
     };
     
+    let color = use_color(color, &out_file);
+
     let data = Data::from_bin(&binary)
         .expect("Failed to parse binary");
-    
-    let mut pc: u16 = 0;
-    while pc != data.ram.len() as u16 {
-        let instr = Operation::next(&data[pc..]).instr();
 
-        writeln!(&mut out_file, "0x{:0>4x}: {:#}", pc, instr)
-            .expect("Failed to write output line");
+    // List the strings instead of disassembling
+    if list_strings {
+        let strings = data.strings(MIN_LISTED_STRING_LEN).into_iter()
+            .filter(|&(addr, _)| (start..end).contains(&(addr as usize)));
+        for (addr, text) in strings {
+            writeln!(&mut out_file, "0x{:0>4x}: {:?}", addr, text)
+                .expect("Failed to write output line");
+        }
+        return;
+    }
+
+    // First pass: decode the listing and collect every literal jump and call target
+    let mut listing = Vec::new();
+    let mut targets = HashSet::new();
+    if let Some(entry) = trace_from {
+        listing = trace_code(&data, entry).into_iter()
+            .filter(|&(addr, _)| (start..end).contains(&(addr as usize)))
+            .collect();
+        targets.extend(listing.iter().filter_map(|(_, item)| match *item {
+            Item::Code(instr) => instr.jump_target(),
+            _ => None,
+        }));
+    } else {
+        // An instruction starting before `end` is decoded in full, but a string stops there
+        let mut pc = start as u16;
+        while (pc as usize) < end {
+            let text_len = data[pc..].iter()
+                .take(end - pc as usize)
+                .take_while(|&&word| is_printable(word))
+                .count();
+
+            if find_strings && text_len >= MIN_STRING_LEN {
+                let text = data[pc..].iter()
+                    .take(text_len)
+                    .map(|&word| word as u8 as char)
+                    .collect();
+                listing.push((pc, Item::Ascii(text)));
+
+                pc += text_len as u16;
+            } else {
+                match Instruction::try_from_words(&data[pc..]) {
+                    Ok(instr) => {
+                        if let Some(dst) = instr.jump_target() {
+                            targets.insert(dst);
+                        }
+                        listing.push((pc, Item::Code(instr)));
+
+                        pc += instr.word_size();
+                    },
+                    Err(e) => {
+                        listing.push((pc, Item::Invalid(e)));
+                        pc += 1;
+                    },
+                }
+            }
+        }
+    }
+
+    // Only targets that land on the start of an instruction get a label
+    let labels = listing.iter()
+        .filter(|&&(addr, ref item)| targets.contains(&addr) && matches!(*item, Item::Code(_)))
+        .map(|&(addr, _)| addr)
+        .collect::<HashSet<_>>();
+
+    if let Some(path) = cfg_file {
+        let mut cfg_out = File::create(path)
+            .expect("Failed to create control flow graph file.");
+        // Skip the zeroed RAM following the end of the binary
+        let cfg_end = cmp::min(binary.len() / 2, end) as u16;
+        cfg::write_dot(&mut cfg_out, &listing, cfg_end)
+            .expect("Failed to write control flow graph");
+    }
 
-        pc += instr.word_size();
+    // Second pass: write the listing with labels at their definitions and references
+    for (addr, item) in listing {
+        if labels.contains(&addr) {
+            writeln!(&mut out_file, "L_{:0>4x}:", addr)
+                .expect("Failed to write output line");
+        }
+        let line = match item {
+            Item::Code(instr) => colorize(&format_instr(&instr, &labels), &instr, color),
+            Item::Ascii(text) => format!(".ascii {:?}", text),
+            Item::Invalid(e) => format!("????  ; {}", e),
+        };
+        match comments.get(&addr) {
+            Some(comment) => writeln!(&mut out_file, "0x{:0>4x}: {:<24} ; {}", addr, line, comment),
+            None => writeln!(&mut out_file, "0x{:0>4x}: {}", addr, line),
+        }.expect("Failed to write output line");
     }
 }
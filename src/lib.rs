@@ -3,14 +3,16 @@
 //! A rust based virtual machine for the Synacor challenge.
 #![warn(missing_docs)]
 
+extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
 
 extern crate byteorder;
-extern crate termion;
-#[macro_use] extern crate chan;
-extern crate chan_signal;
+#[cfg(feature = "cli")] extern crate termion;
+#[cfg(feature = "cli")] #[macro_use] extern crate chan;
+#[cfg(feature = "cli")] extern crate chan_signal;
 
 pub mod cpu;
+pub mod solver;
 
-pub use cpu::{Data, Status, Operation, Instruction, SynCpu, Injection};
+pub use cpu::{Data, Status, Operation, Instruction, SynCpu, Injection, run_program};
@@ -0,0 +1,122 @@
+//! Solvers
+//!
+//! Solutions to the puzzles in the challenge binary that are impractical to
+//! work through in the VM itself.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// All arithmetic in the teleporter's confirmation routine is modulo this value.
+const N: u16 = 1 << 15;
+
+/// The values of r0 and r1 passed to the confirmation routine by the teleporter.
+const TELEPORTER_ARGS: (u16, u16) = (4, 1);
+
+/// The teleporter's confirmation routine for a fixed value of r7, with every
+/// result it computes cached.
+///
+/// The routine is a variant of the Ackermann function:
+///
+/// ```text
+/// f(0, n) = n + 1
+/// f(m, 0) = f(m - 1, r7)
+/// f(m, n) = f(m - 1, f(m, n - 1))
+/// ```
+///
+/// Evaluated directly it takes far too long, but every value wraps modulo 32768,
+/// so for r0 = m there are at most `(m + 1) * 32768` distinct calls and a cache
+/// keyed on both arguments bounds the work.
+pub struct Teleporter {
+    r7: u16,
+    cache: HashMap<(u16, u16), u16>,
+}
+
+impl Teleporter {
+    /// Creates an empty cache for the routine with the given value of r7.
+    pub fn new(r7: u16) -> Teleporter {
+        Teleporter {
+            r7: r7 % N,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Evaluates the routine for the given r0 and r1, returning the final r0.
+    ///
+    /// Calls are kept on an explicit stack rather than recursing, as they nest
+    /// far too deeply for the thread's stack.
+    pub fn eval(&mut self, m: u16, n: u16) -> u16 {
+        let mut pending = vec![(m, n % N)];
+
+        while let Some(&(m, n)) = pending.last() {
+            if self.cache.contains_key(&(m, n)) {
+                pending.pop();
+                continue;
+            }
+
+            // Either the value of this call, or another call it needs first
+            let next = if m == 0 {
+                Ok((n + 1) % N)
+            } else if n == 0 {
+                self.lookup(m - 1, self.r7)
+            } else {
+                match self.lookup(m, n - 1) {
+                    Ok(inner) => self.lookup(m - 1, inner),
+                    Err(call) => Err(call),
+                }
+            };
+
+            match next {
+                Ok(val) => {
+                    self.cache.insert((m, n), val);
+                    pending.pop();
+                },
+                Err(call) => pending.push(call),
+            }
+        }
+
+        self.cache[&(m, n % N)]
+    }
+
+    /// Returns the cached result of a call, or the call itself if it hasn't been evaluated.
+    fn lookup(&self, m: u16, n: u16) -> Result<u16, (u16, u16)> {
+        self.cache.get(&(m, n)).cloned().ok_or((m, n))
+    }
+}
+
+/// Finds the smallest non-zero value of r7 for which the teleporter's confirmation
+/// routine leaves `target_r0` in r0. The challenge expects 6.
+///
+/// The candidates are split between one thread for each available CPU.
+pub fn find_register7(target_r0: u16) -> Option<u16> {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    // The best value found so far, or N if there isn't one yet
+    let best = Arc::new(AtomicUsize::new(N as usize));
+
+    let handles = (0..threads).map(|offset| {
+        let best = best.clone();
+        thread::spawn(move || {
+            let mut r7 = 1 + offset;
+            // Candidates above the best found by another thread can be skipped
+            while r7 < best.load(Ordering::SeqCst) {
+                let (m, n) = TELEPORTER_ARGS;
+                if Teleporter::new(r7 as u16).eval(m, n) == target_r0 {
+                    best.fetch_min(r7, Ordering::SeqCst);
+                    return;
+                }
+                r7 += threads;
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().expect("Solver thread panicked");
+    }
+
+    match best.load(Ordering::SeqCst) {
+        r7 if r7 < N as usize => Some(r7 as u16),
+        _ => None,
+    }
+}
+
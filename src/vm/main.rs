@@ -1,63 +1,192 @@
 extern crate synacor;
 
-use std::io::Read;
+use std::io::{Read, Write};
 use std::fs::File;
 use std::env::args;
+use std::process;
+use std::time::Duration;
 
-use synacor::{SynCpu, Data, Injection};
+use synacor::{SynCpu, Data, Injection, Status};
+
+#[path = "../args.rs"]
+mod args;
+
+use args::Args;
+
+const USAGE: &str = "Usage: synvm [options] <binary>
+
+Options:
+    --replay file         Feed the contents of file to the programme before reading stdin
+    --inject file         Apply the JSON injections in file before running
+    --record file         Save every character read by the programme to file on exit
+    --trace file          Write a JSON trace of every instruction to file on exit
+    --detect-loops steps  Stop if the state sampled every steps instructions repeats
+    --max-steps n         Stop after n instructions
+    --max-stack-depth n   Stop if the stack would grow deeper than n
+    --delay ms            Sleep for ms milliseconds after every instruction
+    --quiet               Don't print the VM's own messages
+
+Exit status:
+    0  The programme halted
+    1  The VM couldn't start, for example because of a bad option or file
+    2  The programme popped or returned from an empty stack
+    3  The programme ran an invalid or unimplemented instruction, or ran off the end of RAM
+    4  The step limit was reached
+    5  A loop was detected
+    6  The programme asked for input after stdin ended
+    7  The VM was interrupted before the programme halted
+    8  The programme wrote to a protected address
+    9  The stack would have grown deeper than --max-stack-depth allows
+    10 The programme divided by zero";
+
+/// The exit status for a VM that couldn't start running the programme.
+const EXIT_STARTUP: i32 = 1;
+
+/// Returns the exit status describing why `cpu` stopped, as listed in `USAGE`.
+fn exit_code(cpu: &SynCpu) -> i32 {
+    if !cpu.halted {
+        return 7;
+    }
+    match cpu.status {
+        Status::Ok => 0,
+        Status::PopOnEmptyStack | Status::ReturnUnderflow => 2,
+        Status::InstructionParseError(..) | Status::UnimplementedInstruction | Status::PcOutOfRange(_) => 3,
+        Status::StepLimitReached => 4,
+        Status::LoopDetected(_) => 5,
+        Status::InputExhausted => 6,
+        Status::WriteProtectViolation(_) => 8,
+        Status::StackOverflow => 9,
+        Status::DivisionByZero(_) => 10,
+        Status::InvalidBinary => EXIT_STARTUP,
+    }
+}
+
+/// Reads the whole of the file at `path`, naming the file in any error.
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buffer))
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(buffer)
+}
 
 fn main() {
+    let args = Args::parse(args().skip(1),
+                           &["--replay", "--inject", "--record", "--trace", "--detect-loops", "--max-steps", "--max-stack-depth",
+                             "--delay"],
+                           &["--quiet"]);
+    let args = match args {
+        Ok(args) if args.positional().len() == 1 => args,
+        Ok(_) => {
+            println!("{}", USAGE);
+            process::exit(EXIT_STARTUP);
+        },
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            process::exit(EXIT_STARTUP);
+        }
+    };
+    let options = (args.parsed::<u64>("--detect-loops"), args.parsed::<u64>("--max-steps"),
+                   args.parsed::<usize>("--max-stack-depth"), args.parsed::<u64>("--delay"));
+    let (loops, max_steps, max_stack_depth, delay) = match options {
+        (Ok(loops), Ok(max_steps), Ok(max_stack_depth), Ok(delay)) => {
+            (loops, max_steps, max_stack_depth, delay.map(Duration::from_millis))
+        },
+        (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+            println!("{}\n\n{}", e, USAGE);
+            process::exit(EXIT_STARTUP);
+        }
+    };
+    let quiet = args.flag("--quiet");
+    let record = args.option("--record");
+    let trace = args.option("--trace");
+
     // Load the binary, replay and injections
-    let binary = if let Some(val) = args().nth(1) {
-        let mut buffer = Vec::new();
-        let mut in_file = File::open(val)
-            .expect("Failed to open challenge binary.");
-        in_file.read_to_end(&mut buffer)
-            .expect("Failed to read in binary contents.");
-        buffer
-    } else {
-        println!("Usage: synvm <binary> [replay] [injections]");
-        return;
+    let files = (read_file(&args.positional()[0]),
+                 args.option("--replay").map(read_file).transpose(),
+                 args.option("--inject").map(read_file).transpose());
+    let (binary, replay, injections) = match files {
+        (Ok(binary), Ok(replay), Ok(injections)) => (binary, replay, injections),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            println!("{}", e);
+            process::exit(EXIT_STARTUP);
+        }
     };
 
-    let replay = if let Some(val) = args().nth(2) {
-        let mut buffer = String::new();
-        let mut replay_file = File::open(val)
-            .expect("Failed to open replay file");
-        replay_file.read_to_string(&mut buffer)
-            .expect("Failed to read in replay file");
-        let mut buffer: Vec<_> = buffer.chars().collect();
-        buffer.reverse();
+    let replay = String::from_utf8_lossy(&replay.unwrap_or_default()).into_owned();
+    if args.option("--replay").is_some() && !quiet {
         println!("Replay buffer loaded");
-        buffer
-    } else {
-        Vec::new()
+    }
+    let injections = match injections {
+        Some(json) => Injection::from_json(&String::from_utf8_lossy(&json)),
+        None => vec![],
     };
 
-    let injections = if let Some(val) = args().nth(3) {
-        let mut buffer = String::new();
-        let mut injection_file = File::open(val)
-            .expect("Failed to open injection file");
-        injection_file.read_to_string(&mut buffer)
-            .expect("Failed to read in injection file");
-        Injection::from_json(&buffer)
-    } else {
-        vec![]
+    // Prepare the CPU
+    let data = match Data::from_bin(&binary) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to decode {}: {}", args.positional()[0], e);
+            process::exit(EXIT_STARTUP);
+        }
     };
 
-    // Prepare the CPU
-    let mut data = Data::from_bin(&binary)
-        .expect("Failed to load decode program binary.");
+    let mut builder = SynCpu::builder(data).input(&replay).quiet().injections(&injections);
+    if let Some(max_steps) = max_steps {
+        builder = builder.max_steps(max_steps);
+    }
+    if let Some(max_stack_depth) = max_stack_depth {
+        builder = builder.max_stack_depth(max_stack_depth);
+    }
+    let mut cpu = match builder.build() {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            println!("Failed to apply injection: {}", e);
+            process::exit(EXIT_STARTUP);
+        }
+    };
+    if record.is_some() {
+        cpu.record = Some(String::new());
+    }
+    if trace.is_some() {
+        cpu.enable_trace();
+    }
+    if let Some(every) = loops {
+        cpu.enable_loop_detection(every);
+    }
+
+    // Run the CPU
+    match delay {
+        Some(delay) => cpu.run_throttled(delay),
+        None => cpu.run(),
+    }
 
-    for injection in injections {
-        injection.inject(&mut data);
+    match cpu.status {
+        Status::LoopDetected(_) | Status::InstructionParseError(..) | Status::StepLimitReached
+            | Status::PcOutOfRange(_) | Status::StackOverflow | Status::DivisionByZero(_) if !quiet => {
+            println!("{}", cpu.status)
+        },
+        _ => {},
     }
 
-    let mut cpu = SynCpu::new(data);
-    cpu.stdin_buf = replay;
-    cpu.loud = false;
+    if cpu.stats.unmatched_rets > 0 && !quiet {
+        println!("Returns with an empty call stack: {}", cpu.stats.unmatched_rets);
+    }
 
-    // Run the CPU
-    cpu.run();
+    let code = exit_code(&cpu);
+
+    if let (Some(path), Some(log)) = (record, cpu.record) {
+        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(log.as_bytes())) {
+            println!("Failed to write recording to {}: {}", path, e);
+        }
+    }
+
+    if let (Some(path), Some(entries)) = (trace, cpu.trace) {
+        let json = synacor::cpu::trace::to_json(&entries).expect("Failed to serialize trace");
+        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(json.as_bytes())) {
+            println!("Failed to write trace to {}: {}", path, e);
+        }
+    }
 
+    process::exit(code);
 }
@@ -0,0 +1,65 @@
+//! Checks that breakpoints stop execution without touching the programme in RAM.
+
+extern crate synacor;
+
+use synacor::{Data, SynCpu};
+use synacor::cpu::Breakpoint;
+
+/// Jumps over two `noop`s to set r0, with the jump target held in an operand word.
+const JUMP: [u16; 8] = [
+    6, 0x0004,      // 0x0000: jmp 0x0004
+    21,             // 0x0002: noop
+    21,             // 0x0003: noop
+    1, 32768, 1,    // 0x0004: set r0 1
+    0,              // 0x0007: halt
+];
+
+/// Converts words to the little endian bytes of a binary.
+fn binary(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|&word| vec![word as u8, (word >> 8) as u8]).collect()
+}
+
+fn cpu() -> SynCpu {
+    let mut cpu = SynCpu::new(Data::from_bin(&binary(&JUMP)).unwrap());
+    cpu.loud = false;
+    cpu
+}
+
+#[test]
+fn breakpoints_leave_ram_and_dumps_untouched() {
+    let mut cpu = cpu();
+    let original = cpu.data.ram.clone();
+    // One on the jump's operand word and one on its target
+    cpu.breakpoints.insert(0x0001, Breakpoint::new(0));
+    cpu.breakpoints.insert(0x0004, Breakpoint::new(0));
+
+    assert_eq!(cpu.data.ram, original);
+    // The same bytes that `dump` writes
+    assert_eq!(binary(&cpu.data.ram[..JUMP.len()]), binary(&JUMP));
+
+    cpu.run();
+    assert_eq!(cpu.pc, 0x0004);
+    assert_eq!(cpu.data.ram, original);
+
+    cpu.breakpoints.remove(&0x0004);
+    cpu.run();
+    assert!(cpu.halted);
+    assert_eq!(cpu.data.registers[0], 1);
+}
+
+#[test]
+fn setting_and_unsetting_breakpoints_leaves_ram_as_it_was() {
+    let mut cpu = cpu();
+    let original = cpu.data.ram.clone();
+    for addr in 0..JUMP.len() as u16 {
+        cpu.breakpoints.insert(addr, Breakpoint::new(0));
+    }
+    for addr in 0..JUMP.len() as u16 {
+        cpu.breakpoints.remove(&addr);
+    }
+
+    assert_eq!(cpu.data.ram, original);
+    cpu.run();
+    assert!(cpu.halted);
+    assert_eq!(cpu.data.registers[0], 1);
+}
@@ -0,0 +1,113 @@
+//! Tests for the instruction decoder, with property tests over arbitrary words.
+
+extern crate synacor;
+extern crate proptest;
+
+use synacor::{Instruction, Operation};
+use synacor::cpu::DecodeError;
+use synacor::cpu::{assembler, instruction};
+use synacor::cpu::syn_int::SynInt;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn decoding_any_words_never_panics(words in vec(any::<u16>(), 0..6)) {
+        let instr = Operation::next(&words).instr();
+        match Instruction::try_from_words(&words) {
+            Ok(decoded) => {
+                prop_assert_eq!(decoded, instr);
+                prop_assert!(decoded.word_size() as usize <= words.len());
+            },
+            Err(_) => prop_assert_eq!(instr, Instruction::_Unknown),
+        }
+    }
+
+    #[test]
+    fn truncated_instructions_report_the_missing_words(opcode in 0..22u16, len in 1..4usize) {
+        let words = vec![opcode; len];
+        let size = Instruction::try_from_words(&[opcode, 0, 0, 0]).unwrap().word_size() as usize;
+        let decoded = Instruction::try_from_words(&words);
+        if len < size {
+            prop_assert_eq!(decoded, Err(DecodeError::Truncated(opcode, size - len)));
+        } else {
+            prop_assert!(decoded.is_ok());
+        }
+    }
+
+    #[test]
+    fn valid_instructions_survive_a_round_trip(opcode in 0..22u16, operands in vec(any::<u16>(), 3)) {
+        let mut words = vec![opcode];
+        words.extend(operands);
+
+        let instr = Instruction::try_from_words(&words).unwrap();
+        let encoded = instr.encode();
+        prop_assert_eq!(&encoded[..], &words[..instr.word_size() as usize]);
+        prop_assert_eq!(Instruction::try_from_words(&encoded), Ok(instr));
+    }
+
+    #[test]
+    fn programmes_survive_a_json_round_trip(words in vec(0..22u16, 1..20), operand in 0..32776u16) {
+        let program = words.iter()
+            .map(|&op| Instruction::try_from_words(&[op, operand, operand, operand]).unwrap())
+            .collect::<Vec<_>>();
+
+        let json = instruction::to_json(&program).unwrap();
+        prop_assert_eq!(instruction::from_json(&json).unwrap(), program);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_reported(opcode in 22..=u16::MAX) {
+        prop_assert_eq!(Instruction::try_from_words(&[opcode, 0, 0, 0]),
+                        Err(DecodeError::UnknownOpcode(opcode)));
+    }
+}
+
+#[test]
+fn a_lone_opcode_is_truncated() {
+    // add needs three operands after its opcode
+    assert_eq!(Operation::next(&[9]).instr(), Instruction::_Unknown);
+    assert_eq!(Operation::next(&[9, 32768, 1, 2]).instr(),
+               Instruction::Add(32768.into(), 1.into(), 2.into()));
+}
+
+#[test]
+fn jumps_set_the_pc_directly() {
+    let jmp = Operation::next(&[6, 0x0010]).instr();
+    let jt = Operation::next(&[7, 32768, 0x0010]).instr();
+    assert_eq!((jmp.size(), jmp.word_size()), (0, 2));
+    assert_eq!((jt.size(), jt.word_size()), (0, 3));
+
+    let add = Operation::next(&[9, 32768, 1, 2]).instr();
+    assert_eq!((add.size(), add.word_size()), (4, 4));
+}
+
+#[test]
+fn json_is_keyed_by_variant() {
+    let program = [Instruction::try_from_words(&[9, 32768, 5, 32771]).unwrap(), Instruction::Halt];
+    assert_eq!(instruction::to_json(&program).unwrap(),
+               r#"[{"Add":[{"Register":0},{"Literal":5},{"Register":3}]},"Halt"]"#);
+}
+
+#[test]
+fn json_without_a_valid_programme_is_rejected() {
+    assert!(instruction::to_json(&[Instruction::_Unknown]).is_err());
+    assert!(instruction::from_json(r#"["_Unknown"]"#).is_err());
+    assert!(instruction::from_json(r#"[{"Push":{"Register":8}}]"#).is_err());
+    assert!(instruction::from_json(r#"[{"Push":{"Literal":32768}}]"#).is_err());
+    assert_eq!(instruction::from_json(r#"[{"Push":{"Literal":32767}}]"#).unwrap(),
+               vec![Instruction::try_from_words(&[2, 32767]).unwrap()]);
+}
+
+#[test]
+fn every_out_literal_reassembles() {
+    for x in 0..32768 {
+        let instr = Instruction::Out(SynInt::Literal(x));
+        assert_eq!(assembler::assemble(&instr.to_string()), Ok(vec![19, x]), "{:?}", instr.to_string());
+    }
+    for r in 0..8 {
+        let instr = Instruction::Out(SynInt::Register(r));
+        assert_eq!(assembler::assemble(&instr.to_string()), Ok(vec![19, 32768 + r as u16]));
+    }
+}
@@ -0,0 +1,144 @@
+//! Checks that the dispatch table evaluates programmes exactly as `step` does.
+
+extern crate synacor;
+
+use synacor::{Data, Status, SynCpu};
+use synacor::cpu::assembler;
+
+/// Prints a fixed string.
+const HELLO: &str = "
+    out 'H'
+    out 'i'
+    out 10
+    halt
+";
+
+/// Echoes its input up to and including the first newline.
+const ECHO: &str = "
+    0x0000: in r0
+    0x0002: out r0
+    0x0004: eq r1 r0 10
+    0x0008: jf r1 0x0000
+    0x000b: halt
+";
+
+/// Pushes two characters and calls a subroutine that pops and prints the top one,
+/// then pops and prints the other on return.
+const STACK: &str = "
+    0x0000: push 'A'
+    0x0002: push 'B'
+    0x0004: call 0x000d
+    0x0006: pop r0
+    0x0008: out r0
+    0x000a: out 10
+    0x000c: halt
+    ; 0x000d: print the value below the return address
+    0x000d: pop r2
+    0x000f: pop r0
+    0x0011: out r0
+    0x0013: push r2
+    0x0015: ret
+";
+
+/// Patches the literal operand of an instruction it has already run, then writes
+/// to RAM it never runs.
+const SELF_MODIFY: &str = "
+    0x0000: set r0 5
+    0x0003: wmem 0x0002 6
+    0x0006: wmem 0x0010 7
+    0x0009: halt
+";
+
+/// Loops through every arithmetic, bitwise and comparison instruction, then
+/// round trips a value through memory and the stack.
+const ARITHMETIC: &str = "
+    0x0000: set r0 10
+    0x0003: add r1 r1 r0
+    0x0007: mult r2 r1 r0
+    0x000b: mod r3 r2 7
+    0x000f: and r4 r2 r3
+    0x0013: or r5 r4 r0
+    0x0017: not r6 r5
+    0x001a: gt r7 r6 r2
+    0x001e: add r0 r0 32767
+    0x0022: jt r0 0x0003
+    0x0025: eq r7 r0 0
+    0x0029: jf r7 0x0000
+    0x002c: wmem 100 r6
+    0x002f: rmem r1 100
+    0x0032: push r1
+    0x0034: call 0x0038
+    0x0036: halt
+    0x0037: noop
+    0x0038: pop r0
+    0x003a: pop r2
+    0x003c: push r0
+    0x003e: ret
+";
+
+/// Creates a CPU running `source` that reads only from `input` and captures its output.
+fn cpu(source: &str, input: &str) -> SynCpu {
+    let words = assembler::assemble(source).unwrap();
+    let mut cpu = SynCpu::new(Data::from_bin(&assembler::to_bytes(&words)).unwrap());
+    cpu.stdin_buf = input.chars().collect();
+    cpu.output = Some(String::new());
+    cpu.read_stdin = false;
+    cpu.loud = false;
+    cpu
+}
+
+/// Runs `cpu` one `step` at a time, never taking the dispatch table's fast path.
+fn run_stepping(cpu: &mut SynCpu) {
+    while !cpu.halted {
+        cpu.step();
+    }
+    cpu.flush_output();
+}
+
+#[test]
+fn the_arithmetic_loop_runs_to_the_end() {
+    let mut cpu = cpu(ARITHMETIC, "");
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(cpu.pc, 0x0036);
+    assert!(cpu.call_stack.is_empty());
+}
+
+#[test]
+fn dispatch_matches_step() {
+    let programs = [(HELLO, ""), (ECHO, "hello\n"), (ECHO, "hello"), (STACK, ""), (SELF_MODIFY, ""),
+                    (ARITHMETIC, ""), ("pop r0", ""), ("set r1 0\nmod r0 5 r1", "")];
+    for &(source, input) in programs.iter() {
+        let mut dispatched = cpu(source, input);
+        dispatched.run_headless();
+        let mut stepped = cpu(source, input);
+        run_stepping(&mut stepped);
+
+        assert_eq!(dispatched.status, stepped.status, "{}", source);
+        assert_eq!(dispatched.pc, stepped.pc, "{}", source);
+        assert_eq!(dispatched.data.registers, stepped.data.registers, "{}", source);
+        assert_eq!(dispatched.data.stack, stepped.data.stack, "{}", source);
+        assert_eq!(dispatched.data.ram, stepped.data.ram, "{}", source);
+        assert_eq!(dispatched.call_stack, stepped.call_stack, "{}", source);
+        assert_eq!(dispatched.output, stepped.output, "{}", source);
+    }
+}
+
+#[test]
+fn writes_past_the_end_of_ram_are_checked_as_in_step() {
+    // The write through r0 wraps around to 0x0002, which is both protected and watched
+    let mut protected = cpu("set r1 5\nwmem r0 7\nhalt", "");
+    protected.data.registers[0] = 0x8002;
+    protected.protected.push(0..8);
+    protected.run_headless();
+    assert_eq!(protected.status, Status::WriteProtectViolation(0x0002));
+    assert_eq!(protected.data.ram[0x0002], 5);
+
+    let mut watched = cpu("set r1 5\nwmem r0 7\nhalt", "");
+    watched.data.registers[0] = 0x8002;
+    watched.watchpoints.insert(0x0002);
+    watched.run_headless();
+    assert_eq!(watched.watch_hit, Some((0x0002, 5, 7)));
+    assert_eq!(watched.pc, 0x0006);
+}
@@ -0,0 +1,44 @@
+//! Tests for the debugger's expression evaluator.
+
+extern crate synacor;
+
+#[path = "../src/debugger/expr.rs"]
+mod expr;
+
+use expr::eval;
+
+#[test]
+fn follows_operator_precedence() {
+    let registers = [2, 3, 0, 0, 0, 0, 0, 0];
+    assert_eq!(eval("r0 + r1 * 4", &registers), Ok(14));
+    assert_eq!(eval("(r0 + r1) * 4", &registers), Ok(20));
+    assert_eq!(eval("r1 | r0 & 1", &registers), Ok(3));
+}
+
+#[test]
+fn wraps_modulo_32768() {
+    let registers = [0; 8];
+    assert_eq!(eval("1 - 2", &registers), Ok(32767));
+    assert_eq!(eval("32767 + 2", &registers), Ok(1));
+    assert_eq!(eval("16384 * 4", &registers), Ok(0));
+}
+
+#[test]
+fn reduces_registers_past_32767() {
+    // r1 and r2 reduce to 7232 and 32767
+    let registers = [0, 40000, 0xffff, 0, 0, 0, 0, 0];
+    assert_eq!(eval("r0 - r1", &registers), Ok(25536));
+    assert_eq!(eval("r1 + r2", &registers), Ok(7231));
+    assert_eq!(eval("r2 * r2", &registers), Ok(1));
+    assert_eq!(eval("r2 % r1", &registers), Ok(3839));
+    assert_eq!(eval("r2 & r1", &registers), Ok(7232));
+}
+
+#[test]
+fn reports_malformed_expressions() {
+    let registers = [0; 8];
+    assert!(eval("1 +", &registers).is_err());
+    assert!(eval("(1 + 2", &registers).is_err());
+    assert!(eval("1 % 0", &registers).is_err());
+    assert!(eval("r9", &registers).is_err());
+}
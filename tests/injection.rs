@@ -0,0 +1,58 @@
+//! Tests for injections read from JSON.
+
+extern crate synacor;
+
+use synacor::{Data, Injection};
+use synacor::cpu::injection::InjectionError;
+
+/// Applies every injection in `json` to empty RAM.
+fn inject(json: &str) -> Data {
+    let mut data = Data::from_bin(&[]).unwrap();
+    let injections = Injection::from_json(json);
+    assert!(!injections.is_empty(), "no injections in {}", json);
+    for injection in injections {
+        injection.inject(&mut data).unwrap();
+    }
+    data
+}
+
+#[test]
+fn presets_registers() {
+    let data = inject(r#"[{"registers": [[7, 25734]], "stack": [1, 2]}]"#);
+    assert_eq!(data.registers[7], 25734);
+    assert_eq!(&data.registers[..7], &[0; 7]);
+    assert_eq!(data.stack, vec![1, 2]);
+}
+
+#[test]
+fn reads_payload_only_injections() {
+    let data = inject(r#"[{"addr": 5489, "payload": [21, 21]}]"#);
+    assert_eq!(&data.ram[5488..5492], &[0, 21, 21, 0]);
+    assert_eq!(data.registers, [0; 8]);
+    assert!(data.is_stack_empty());
+}
+
+#[test]
+fn payloads_past_the_end_of_ram_are_rejected() {
+    let mut data = Data::from_bin(&[]).unwrap();
+    let injections = Injection::from_json(r#"[{"addr": 32766, "payload": [1, 2, 3]}]"#);
+    assert_eq!(injections[0].inject(&mut data), Err(InjectionError::OutOfBounds(32766, 3)));
+    assert_eq!(&data.ram[32766..], &[0, 0]);
+}
+
+#[test]
+fn asm_matches_the_equivalent_payload() {
+    let asm = inject(r#"[{"addr": 5489, "asm": "set r0 6\nnoop\nout 'x'"}]"#);
+    let payload = inject(r#"[{"addr": 5489, "payload": [1, 32768, 6, 21, 19, 120]}]"#);
+    assert_eq!(asm.ram, payload.ram);
+    assert_eq!(&asm.ram[5489..5495], &[1, 32768, 6, 21, 19, 120]);
+
+    let mut data = Data::from_bin(&[]).unwrap();
+    Injection::from_asm(5489, "set r0 6\nnoop\nout 'x'").unwrap().inject(&mut data).unwrap();
+    assert_eq!(data.ram, payload.ram);
+}
+
+#[test]
+fn asm_and_payload_together_are_rejected() {
+    assert!(Injection::from_json(r#"[{"addr": 0, "asm": "noop", "payload": [21]}]"#).is_empty());
+}
@@ -0,0 +1,338 @@
+//! Runs small hand assembled programmes to completion and checks what they print.
+
+extern crate synacor;
+
+use synacor::{Data, Status, SynCpu, run_program};
+use synacor::cpu::{assembler, DecodeError, MemoryDevice};
+
+use std::sync::{Arc, Mutex};
+
+/// Prints a fixed string.
+const HELLO: &str = "
+    out 'H'
+    out 'i'
+    out 10
+    halt
+";
+
+/// Echoes its input up to and including the first newline.
+const ECHO: &str = "
+    0x0000: in r0
+    0x0002: out r0
+    0x0004: eq r1 r0 10
+    0x0008: jf r1 0x0000
+    0x000b: halt
+";
+
+/// Pushes two characters and calls a subroutine that pops and prints the top one,
+/// then pops and prints the other on return.
+const STACK: &str = "
+    0x0000: push 'A'
+    0x0002: push 'B'
+    0x0004: call 0x000d
+    0x0006: pop r0
+    0x0008: out r0
+    0x000a: out 10
+    0x000c: halt
+    ; 0x000d: print the value below the return address
+    0x000d: pop r2
+    0x000f: pop r0
+    0x0011: out r0
+    0x0013: push r2
+    0x0015: ret
+";
+
+/// Reads from and writes to an address claimed by a device, then RAM either side of it.
+const DEVICE: &str = "
+    0x0000: rmem r0 0x1000
+    0x0003: rmem r1 0x1000
+    0x0006: wmem 0x1000 r1
+    0x0009: wmem 0x1001 r1
+    0x000c: rmem r2 0x0fff
+    0x000f: halt
+";
+
+/// Patches the literal operand of an instruction it has already run, then writes
+/// to RAM it never runs.
+const SELF_MODIFY: &str = "
+    0x0000: set r0 5
+    0x0003: wmem 0x0002 6
+    0x0006: wmem 0x0010 7
+    0x0009: halt
+";
+
+/// Patches the literal operand of an instruction it has already run through an
+/// address held in r0.
+const SELF_MODIFY_THROUGH_REGISTER: &str = "
+    0x0000: set r1 5
+    0x0003: wmem r0 7
+    0x0006: halt
+";
+
+/// A device at 0x1000 counting its reads and logging its writes.
+#[derive(Default)]
+struct Counter {
+    reads: u16,
+    writes: Vec<u16>,
+}
+
+impl MemoryDevice for Counter {
+    fn read(&mut self, addr: u16) -> Option<u16> {
+        if addr != 0x1000 {
+            return None;
+        }
+        self.reads += 1;
+        Some(self.reads)
+    }
+
+    fn write(&mut self, addr: u16, val: u16) -> bool {
+        if addr == 0x1000 {
+            self.writes.push(val);
+        }
+        addr == 0x1000
+    }
+}
+
+fn binary(source: &str) -> Vec<u8> {
+    assembler::to_bytes(&assembler::assemble(source).unwrap())
+}
+
+/// Creates a CPU running `source` that reads only from `input` and captures its output.
+fn cpu(source: &str, input: &str) -> SynCpu {
+    SynCpu::builder(Data::from_words(&assembler::assemble(source).unwrap()).unwrap())
+        .input(input)
+        .read_stdin(false)
+        .capture_output()
+        .quiet()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn prints_a_string() {
+    assert_eq!(run_program(&binary(HELLO), ""), Ok("Hi\n".to_string()));
+}
+
+#[test]
+fn echoes_a_line_of_input() {
+    assert_eq!(run_program(&binary(ECHO), "hello\nworld\n"), Ok("hello\n".to_string()));
+}
+
+#[test]
+fn reports_running_out_of_input() {
+    assert_eq!(run_program(&binary(ECHO), "hello"), Err(Status::InputExhausted));
+}
+
+#[test]
+fn output_hidden_by_echo_stays_buffered() {
+    let mut cpu = SynCpu::builder(Data::from_words(&assembler::assemble(HELLO).unwrap()).unwrap())
+        .quiet()
+        .build()
+        .unwrap();
+    // out 'H' with echo on, never reaching a newline to flush it
+    cpu.step();
+    cpu.echo = false;
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(cpu.buffered_output(), "Hi\n");
+}
+
+#[test]
+fn calls_and_returns_through_the_stack() {
+    let mut cpu = cpu(STACK, "");
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(cpu.output.as_ref().unwrap(), "BA\n");
+    assert!(cpu.data.is_stack_empty());
+    assert!(cpu.call_stack.is_empty());
+    assert_eq!(cpu.data.registers[2], 0x0006);
+}
+
+#[test]
+fn stops_at_the_step_limit() {
+    let mut cpu = cpu(ECHO, "hello\n");
+    cpu.max_steps = Some(6);
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::StepLimitReached);
+    assert_eq!(cpu.output.as_ref().unwrap(), "he");
+    assert_eq!(cpu.stats.steps, 6);
+}
+
+#[test]
+fn counts_the_cost_of_each_instruction() {
+    let mut cpu = cpu(STACK, "");
+    cpu.run_headless();
+
+    // Three each of push, pop and out, a call, a ret and a halt
+    assert_eq!(cpu.stats.steps, 12);
+    assert_eq!(cpu.stats.cycles, 3 * (2 + 2 + 2) + 3 + 3 + 1);
+}
+
+#[test]
+fn stops_at_the_stack_depth_limit() {
+    // Recurses forever, pushing a word and a return address per level
+    let mut cpu = cpu("push r0\ncall 0", "");
+    cpu.max_stack_depth = Some(5);
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::StackOverflow);
+    assert_eq!(cpu.data.stack_depth(), 5);
+    assert_eq!(cpu.call_stack.len(), 2);
+    assert_eq!(cpu.pc, 0x0002);
+}
+
+#[test]
+fn steps_through_in_with_pushed_input() {
+    // Allowed to read stdin, but never needs to while input is queued
+    let mut cpu = SynCpu::new(Data::from_words(&assembler::assemble(ECHO).unwrap()).unwrap());
+    cpu.loud = false;
+    cpu.push_input("h");
+    cpu.push_input("i");
+
+    cpu.step();
+    assert_eq!(cpu.data.registers[0], 'h' as u16);
+    assert_eq!(cpu.pc, 0x0002);
+    cpu.pc = 0x0000;
+    cpu.step();
+    assert_eq!(cpu.data.registers[0], 'i' as u16);
+    assert!(cpu.stdin_buf.is_empty());
+    assert!(!cpu.halted);
+}
+
+#[test]
+fn pop_on_an_empty_stack_faults() {
+    assert_eq!(run_program(&binary("pop r0"), ""), Err(Status::PopOnEmptyStack));
+}
+
+#[test]
+fn counts_returns_without_a_call() {
+    let mut cpu = cpu("push 3\nret\nhalt", "");
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(cpu.stats.unmatched_rets, 1);
+    assert_eq!(cpu.pc, 0x0003);
+}
+
+#[test]
+fn ret_on_an_empty_stack_faults() {
+    assert_eq!(run_program(&binary("out 'x'\nret"), ""), Err(Status::ReturnUnderflow));
+}
+
+#[test]
+fn memory_devices_handle_the_addresses_they_map() {
+    let mut cpu = cpu(DEVICE, "");
+    let counter = cpu.add_device(Counter::default());
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(&cpu.data.registers[..3], &[1, 2, 0]);
+    assert_eq!(counter.lock().unwrap().writes, vec![2]);
+    assert_eq!(cpu.data.ram[0x1000], 0);
+    assert_eq!(cpu.data.ram[0x1001], 2);
+}
+
+#[test]
+fn writes_to_executed_code_are_reported() {
+    let mut cpu = cpu(SELF_MODIFY, "");
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let log = writes.clone();
+    cpu.on_self_modify(move |pc, addr, old, new| log.lock().unwrap().push((pc, addr, old, new)));
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(*writes.lock().unwrap(), vec![(0x0003, 0x0002, 5, 6)]);
+    let coverage = cpu.coverage.as_ref().unwrap();
+    assert!(coverage[..0x000a].iter().all(|&executed| executed));
+    assert!(!coverage[0x0010]);
+}
+
+#[test]
+fn writes_past_the_end_of_ram_wrap_around_when_reported() {
+    let mut cpu = cpu(SELF_MODIFY_THROUGH_REGISTER, "");
+    cpu.data.registers[0] = 0x8002;
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let log = writes.clone();
+    cpu.on_self_modify(move |pc, addr, old, new| log.lock().unwrap().push((pc, addr, old, new)));
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::Ok);
+    assert_eq!(*writes.lock().unwrap(), vec![(0x0003, 0x0002, 5, 7)]);
+    assert_eq!(cpu.data.ram[0x0002], 7);
+}
+
+#[test]
+fn writes_past_the_end_of_ram_respect_protection() {
+    let mut cpu = cpu(SELF_MODIFY_THROUGH_REGISTER, "");
+    cpu.data.registers[0] = 0x8000;
+    cpu.protected.push(0..8);
+    // set r1 5, then wmem r0 7
+    cpu.step();
+    cpu.step();
+
+    assert_eq!(cpu.status, Status::WriteProtectViolation(0x0000));
+    assert!(cpu.halted);
+    assert_eq!(cpu.data.ram[0x0000], 1);
+}
+
+#[test]
+fn writes_past_the_end_of_ram_hit_watchpoints() {
+    let mut cpu = cpu(SELF_MODIFY_THROUGH_REGISTER, "");
+    cpu.data.registers[0] = 0x8002;
+    cpu.watchpoints.insert(0x0002);
+    // set r1 5, then wmem r0 7
+    cpu.step();
+    cpu.step();
+
+    assert_eq!(cpu.watch_hit, Some((0x0002, 5, 7)));
+    assert_eq!(cpu.data.ram[0x0002], 7);
+}
+
+/// Creates a CPU with `words` at the end of RAM and the pc on the first of them.
+fn cpu_at_end(words: &[u16]) -> SynCpu {
+    let mut data = Data::from_words(&[]).unwrap();
+    let start = data.ram.len() - words.len();
+    data.ram[start..].copy_from_slice(words);
+
+    let mut cpu = SynCpu::builder(data).read_stdin(false).capture_output().quiet().build().unwrap();
+    cpu.pc = start as u16;
+    cpu
+}
+
+#[test]
+fn instructions_cut_off_by_the_end_of_ram_fault() {
+    // add r0 r1 at 0x7ffe, missing its last operand
+    let mut cpu = cpu_at_end(&[9, 0x8000]);
+    assert_eq!(cpu.pc, 0x7ffe);
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::InstructionParseError(0x7ffe, DecodeError::Truncated(9, 2)));
+}
+
+#[test]
+fn running_off_the_end_of_ram_faults() {
+    // push 5 as the last instruction in RAM
+    let mut cpu = cpu_at_end(&[2, 5]);
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::PcOutOfRange(0x8000));
+    assert_eq!(cpu.data.stack, vec![5]);
+}
+
+#[test]
+fn jumping_past_the_end_of_ram_faults() {
+    // jmp r0, with r0 holding an address no literal can reach
+    let mut cpu = cpu_at_end(&[6, 0x8000]);
+    cpu.data.registers[0] = 0xffff;
+    cpu.run_headless();
+
+    assert_eq!(cpu.status, Status::PcOutOfRange(0xffff));
+}
+
+#[test]
+fn dividing_by_zero_faults() {
+    assert_eq!(run_program(&binary("set r1 0\nmod r0 5 r1"), ""), Err(Status::DivisionByZero(0x0003)));
+}
@@ -0,0 +1,23 @@
+//! Tests for the puzzle solvers.
+
+extern crate synacor;
+
+use synacor::solver::{find_register7, Teleporter};
+
+#[test]
+fn the_teleporter_confirms_the_known_register() {
+    assert_eq!(Teleporter::new(25734).eval(4, 1), 6);
+}
+
+#[test]
+fn finds_the_smallest_register() {
+    // 1 is the smallest candidate, so is found for whatever it produces
+    let target = Teleporter::new(1).eval(4, 1);
+    assert_eq!(find_register7(target), Some(1));
+}
+
+#[test]
+#[ignore = "tries 25734 candidates, so takes minutes even in release builds"]
+fn finds_the_register_the_challenge_expects() {
+    assert_eq!(find_register7(6), Some(25734));
+}